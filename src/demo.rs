@@ -1,21 +1,28 @@
 use crate::{
     figure::{
+        ai::{MonsterAbilities, MonsterAbility, MonsterActionCommand},
         attack::{Attack, AttackCommand},
-        condition::{AddConditionCommand, ConditionKind, Conditions, RemoveConditionCommand},
-        health::Health,
-        modifier::{Modifier, ModifierTray},
-        movement::MoveCommand,
-        FigureBundle, FigureId,
+        bonus::{AddBonusCommand, BonusDuration, Retaliate},
+        condition::{AddConditionCommand, ConditionKind, RemoveConditionCommand},
+        health::{HealCommand, Health},
+        modifier::{deck::ModifierDeck, Modifier, ModifierTray},
+        movement::{MoveCommand, MovementKind},
+        FigureId, FigureInstance, Initiatives, Team,
+    },
+    game::RoundState,
+    scenario::{
+        definition::{spawn_scenario_on_load, CharacterSetup, ScenarioSpawned, ScenarioToSpawn},
+        map::{HexGrid, HexLayer, HexPosition, OverlayKind},
     },
-    scenario::map::{HexGrid, HexLayer, HexPosition},
 };
 use bevy::{
     asset::RenderAssetUsages,
-    color::palettes::css::{AQUA, GREEN, RED, WHITE},
+    color::palettes::css::{AQUA, BLACK, BLUE, GRAY, GREEN, ORANGE, RED, SADDLE_BROWN, WHITE},
     prelude::*,
     render::mesh::{Indices, PrimitiveTopology},
+    utils::HashMap,
 };
-use hexx::{shapes, Hex, HexLayout, HexOrientation, PlaneMeshBuilder};
+use hexx::{Hex, HexLayout, HexOrientation, PlaneMeshBuilder};
 
 use crate::scenario::command::ScenarioCommandQueue;
 
@@ -23,108 +30,269 @@ pub struct DemoPlugin;
 
 const HEX_SIZE: Vec2 = Vec2::splat(20.0);
 
+/* Relative to the assets folder */
+pub const DEMO_SCENARIO_PATH: &str = "scenarios/demo.scenario.ron";
+
 impl Plugin for DemoPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup);
+        app.add_systems(Startup, setup)
+            .add_systems(OnEnter(RoundState::CardSelection), select_demo_cards)
+            .add_systems(
+                Update,
+                (
+                    queue_demo_on_spawn.after(spawn_scenario_on_load),
+                    add_ground_visuals,
+                    add_wall_visuals,
+                    add_overlay_visuals,
+                    add_figure_visuals,
+                    hide_dead_figures,
+                ),
+            );
     }
 }
 
+/* Meshes and materials to visualize whatever is spawned on the map */
+#[derive(Debug, Resource)]
+struct DemoAssets {
+    mesh: Handle<Mesh>,
+    aqua_material: Handle<ColorMaterial>,
+    white_material: Handle<ColorMaterial>,
+    red_material: Handle<ColorMaterial>,
+    green_material: Handle<ColorMaterial>,
+    blue_material: Handle<ColorMaterial>,
+    black_material: Handle<ColorMaterial>,
+    brown_material: Handle<ColorMaterial>,
+    orange_material: Handle<ColorMaterial>,
+    gray_material: Handle<ColorMaterial>,
+}
+
 fn setup(
     mut commands: Commands,
+    asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    mut command_queue: ResMut<ScenarioCommandQueue>,
 ) {
     commands.spawn(Camera2d);
 
-    let layout = HexLayout {
+    commands.insert_resource(DemoAssets {
+        mesh: meshes.add(hexagonal_plane(&demo_layout())),
+        aqua_material: materials.add(Color::from(AQUA)),
+        white_material: materials.add(Color::from(WHITE)),
+        red_material: materials.add(Color::from(RED)),
+        green_material: materials.add(Color::from(GREEN)),
+        blue_material: materials.add(Color::from(BLUE)),
+        black_material: materials.add(Color::from(BLACK)),
+        brown_material: materials.add(Color::from(SADDLE_BROWN)),
+        orange_material: materials.add(Color::from(ORANGE)),
+        gray_material: materials.add(Color::from(GRAY)),
+    });
+
+    commands.insert_resource(ScenarioToSpawn {
+        definition: asset_server.load(DEMO_SCENARIO_PATH),
+        layout: demo_layout(),
+        characters: demo_characters(),
+    });
+}
+
+/* There is no card selection yet, so the same initiatives and abilities are played every round */
+fn select_demo_cards(
+    mut initiatives: ResMut<Initiatives>,
+    mut monster_abilities: ResMut<MonsterAbilities>,
+) {
+    initiatives.set(FigureId::new(0), 23);
+    initiatives.set(FigureId::new(1), 41);
+    monster_abilities.set(FigureId::new(1), DEMO_MONSTER_ABILITY, 3);
+}
+
+const DEMO_MONSTER_ABILITY: MonsterAbility = MonsterAbility {
+    movement: 2,
+    movement_kind: MovementKind::Default,
+    range: 1,
+    targets: 1,
+    pierce: 0,
+    shield: 0,
+};
+
+pub fn demo_layout() -> HexLayout {
+    HexLayout {
         orientation: HexOrientation::Flat,
         scale: HEX_SIZE,
         ..default()
-    };
-    let mesh = meshes.add(hexagonal_plane(&layout));
-
-    let aqua_material = materials.add(Color::from(AQUA));
-    let white_material = materials.add(Color::from(WHITE));
-    let red_material = materials.add(Color::from(RED));
-    let green_material = materials.add(Color::from(GREEN));
-
-    let entities: Vec<Entity> = shapes::flat_rectangle([-2, 2, -3, 2])
-        .map(|hex| {
-            let entity = commands
-                .spawn((
-                    Mesh2d(mesh.clone()),
-                    MeshMaterial2d(white_material.clone()),
-                    HexPosition::new(hex, HexLayer::Ground),
-                ))
-                .observe(update_material_on::<Pointer<Over>>(aqua_material.clone()))
-                .observe(update_material_on::<Pointer<Out>>(white_material.clone()))
-                .with_children(|b| {
-                    b.spawn((
-                        Text2d(format!("{},{}", hex.x, hex.y)),
-                        TextColor(Color::BLACK),
-                        TextFont {
-                            font_size: 7.0,
-                            ..default()
-                        },
-                        Transform::from_xyz(0.0, 0.0, 10.0),
-                    ));
-                })
-                .id();
-            entity
-        })
-        .collect();
-
-    let figure_a = commands
-        .spawn(FigureBundle {
-            mesh_2d: Mesh2d(mesh.clone()),
-            mesh_material_2d: MeshMaterial2d(green_material.clone()),
-            hex_position: HexPosition::new(Hex::new(0, 0), HexLayer::Figure),
-            health: Health::new(12),
-            conditions: Conditions::new(&[]),
-            id: FigureId::new(0),
-        })
-        .id();
-
-    let figure_b = commands
-        .spawn(FigureBundle {
-            mesh_2d: Mesh2d(mesh.clone()),
-            mesh_material_2d: MeshMaterial2d(red_material.clone()),
-            hex_position: HexPosition::new(Hex::new(2, 1), HexLayer::Figure),
-            health: Health::new(12),
-            conditions: Conditions::new(&[ConditionKind::Muddle]),
-            id: FigureId::new(1),
-        })
-        .id();
-
-    commands.spawn(ModifierTray::new(
-        FigureId::new(0),
-        [
-            [Modifier::zero(), Modifier::crit(), Modifier::zero()],
-            [Modifier::zero(), Modifier::zero(), Modifier::zero()],
-            [Modifier::zero(), Modifier::zero(), Modifier::zero()],
-            [Modifier::zero(), Modifier::zero(), Modifier::zero()],
-            [Modifier::zero(), Modifier::zero(), Modifier::zero()],
-            [Modifier::zero(), Modifier::zero(), Modifier::zero()],
-        ],
-    ));
-
-    commands
-        .spawn(HexGrid::new(layout))
-        .add_children(&entities)
-        .add_children(&[figure_a, figure_b]);
+    }
+}
+
+/* The characters the players bring into the demo scenario */
+pub fn demo_characters() -> Vec<CharacterSetup> {
+    vec![CharacterSetup {
+        id: FigureId::new(0),
+        health: 12,
+        modifier_tray: ModifierTray::new(
+            FigureId::new(0),
+            [
+                [Modifier::zero(), Modifier::crit(), Modifier::zero()],
+                [Modifier::zero(), Modifier::zero(), Modifier::zero()],
+                [Modifier::zero(), Modifier::zero(), Modifier::zero()],
+                [Modifier::zero(), Modifier::zero(), Modifier::zero()],
+                [Modifier::zero(), Modifier::zero(), Modifier::zero()],
+                [Modifier::zero(), Modifier::zero(), Modifier::zero()],
+            ],
+        ),
+        modifier_deck: ModifierDeck::standard(FigureId::new(0)),
+    }]
+}
 
+fn queue_demo_on_spawn(
+    mut scenario_spawned: EventReader<ScenarioSpawned>,
+    mut command_queue: ResMut<ScenarioCommandQueue>,
+) {
+    for ScenarioSpawned { figures } in scenario_spawned.read() {
+        queue_demo_commands(&mut command_queue, figures);
+    }
+}
+
+/// Queue up some commands to step through in the spawned demo scenario
+/// This does not need any rendering, so it is also used for headless simulation
+pub fn queue_demo_commands(
+    command_queue: &mut ScenarioCommandQueue,
+    figures: &HashMap<(FigureId, FigureInstance), Entity>,
+) {
+    let figure_a = figures[&(FigureId::new(0), FigureInstance::new(0))];
+    let figure_b = figures[&(FigureId::new(1), FigureInstance::new(0))];
+
+    let poison = AddConditionCommand::new(figure_b, ConditionKind::Poison);
+    command_queue.queue(vec![poison.into()]);
+    command_queue.queue_transaction(vec![
+        MoveCommand::new(figure_a, Hex::new(1, 0), 1).into(),
+        MoveCommand::new(figure_a, Hex::new(1, 1), 1).into(),
+    ]);
     let new_commands = vec![
-        AddConditionCommand::new(figure_b, ConditionKind::Poison).into(),
-        MoveCommand::new(figure_a, Hex::new(1, 0)).into(),
-        MoveCommand::new(figure_a, Hex::new(1, 1)).into(),
         AttackCommand::new(figure_a, Attack::new(figure_b, 2)).into(),
         AddConditionCommand::new(figure_b, ConditionKind::Muddle).into(),
         AddConditionCommand::new(figure_b, ConditionKind::Wound).into(),
         RemoveConditionCommand::new(figure_b, ConditionKind::Muddle).into(),
-        RemoveConditionCommand::new(figure_b, ConditionKind::Wound).into(),
+        /* Poison cancels the heal, but it still removes Poison and Wound */
+        HealCommand::new(figure_a, figure_b, 2).into(),
+        AddBonusCommand::new(figure_a, BonusDuration::Round)
+            .with_retaliate(Retaliate::new(1, 1))
+            .into(),
     ];
     command_queue.queue(new_commands);
+
+    /* The monster decides on its own where to move and whom to attack */
+    command_queue.queue(vec![MonsterActionCommand::new(
+        figure_b,
+        DEMO_MONSTER_ABILITY,
+        3,
+    )
+    .into()]);
+}
+
+fn add_ground_visuals(
+    mut commands: Commands,
+    demo_assets: Res<DemoAssets>,
+    hex_positions: Query<(Entity, &HexPosition), Added<HexPosition>>,
+) {
+    for (entity, hex_position) in &hex_positions {
+        let HexLayer::Ground = hex_position.layer() else {
+            continue;
+        };
+        let hex = hex_position.hex();
+
+        commands
+            .entity(entity)
+            .insert((
+                Mesh2d(demo_assets.mesh.clone()),
+                MeshMaterial2d(demo_assets.white_material.clone()),
+            ))
+            .observe(update_material_on::<Pointer<Over>>(
+                demo_assets.aqua_material.clone(),
+            ))
+            .observe(update_material_on::<Pointer<Out>>(
+                demo_assets.white_material.clone(),
+            ))
+            .with_children(|b| {
+                b.spawn((
+                    Text2d(format!("{},{}", hex.x, hex.y)),
+                    TextColor(Color::BLACK),
+                    TextFont {
+                        font_size: 7.0,
+                        ..default()
+                    },
+                    Transform::from_xyz(0.0, 0.0, 10.0),
+                ));
+            });
+    }
+}
+
+/* Walls are no entities of their own, so they only get visuals as children of the HexGrid */
+fn add_wall_visuals(
+    mut commands: Commands,
+    demo_assets: Res<DemoAssets>,
+    hex_grids: Query<(Entity, &HexGrid), Added<HexGrid>>,
+) {
+    for (entity, hex_grid) in &hex_grids {
+        commands.entity(entity).with_children(|b| {
+            for wall in hex_grid.walls() {
+                let pos = hex_grid.layout().hex_to_world_pos(*wall);
+
+                b.spawn((
+                    Mesh2d(demo_assets.mesh.clone()),
+                    MeshMaterial2d(demo_assets.gray_material.clone()),
+                    Transform::from_xyz(pos.x, pos.y, HexLayer::Ground.z())
+                        .with_scale(HexLayer::Ground.scale()),
+                ));
+            }
+        });
+    }
+}
+
+fn add_overlay_visuals(
+    mut commands: Commands,
+    demo_assets: Res<DemoAssets>,
+    overlays: Query<(Entity, &OverlayKind), Added<OverlayKind>>,
+) {
+    for (entity, overlay_kind) in &overlays {
+        let material = match overlay_kind {
+            OverlayKind::Obstacle => demo_assets.black_material.clone(),
+            OverlayKind::DifficultTerrain => demo_assets.brown_material.clone(),
+            OverlayKind::HazardousTerrain | OverlayKind::Trap => {
+                demo_assets.orange_material.clone()
+            }
+        };
+
+        commands
+            .entity(entity)
+            .insert((Mesh2d(demo_assets.mesh.clone()), MeshMaterial2d(material)));
+    }
+}
+
+fn add_figure_visuals(
+    mut commands: Commands,
+    demo_assets: Res<DemoAssets>,
+    figures: Query<(Entity, &Team), Added<Team>>,
+) {
+    for (entity, team) in &figures {
+        let material = match team {
+            Team::Monster => demo_assets.red_material.clone(),
+            Team::Player => demo_assets.green_material.clone(),
+            Team::Ally => demo_assets.blue_material.clone(),
+        };
+
+        commands
+            .entity(entity)
+            .insert((Mesh2d(demo_assets.mesh.clone()), MeshMaterial2d(material)));
+    }
+}
+
+/* Dead figures are off the map, undoing their death brings them back */
+fn hide_dead_figures(mut figures: Query<(&Health, &mut Visibility), Changed<Health>>) {
+    for (health, mut visibility) in &mut figures {
+        *visibility = if health.is_dead() {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
 }
 
 fn hexagonal_plane(hex_layout: &HexLayout) -> Mesh {
@@ -1,24 +1,79 @@
 // Monster AI
 
-use bevy::{prelude::*, utils::{hashbrown::hash_map::Entry, HashMap}};
-use hexx::Hex;
+use std::cmp::{Ordering, Reverse};
 
-use crate::scenario::{command::ScenarioCommand, map::HexGrid};
+use bevy::{ecs::entity::EntityMapper, prelude::*, utils::HashMap};
+use hexx::Hex;
 
-use super::{condition::{ConditionKind, Conditions}, movement::MovementKind, Team};
+use crate::{
+    game::{EndTurnCommand, StartOfTurn},
+    scenario::{
+        command::{
+            ScenarioCommand, ScenarioCommandQueue, ScenarioCommandTrait,
+            ScenarionCommandExecuteResult,
+        },
+        map::{HexGrid, HexLayer, HexPosition},
+        pathfinding::MovementMap,
+    },
+};
+
+#[cfg(test)]
+mod tests;
+
+use super::{
+    attack::{Attack, AttackCommand},
+    bonus::{AddBonusCommand, BonusDuration, Shield},
+    condition::{ConditionKind, Conditions},
+    movement::{MoveCommand, MovementKind},
+    FigureId, Initiatives, Team,
+};
 
 // TODO: "Monster" kind as well?
-#[derive(Debug, Default, Hash, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct Initiative {
     value: usize,
     summon: bool,
     id: usize,
 }
 
-#[derive(Debug, Default, Hash, PartialEq, Eq)]
+impl Initiative {
+    pub fn new(value: usize, summon: bool, id: usize) -> Self {
+        Self { value, summon, id }
+    }
+}
+
+// Lower initiative acts first, summons act right before their summoner
+impl Ord for Initiative {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value
+            .cmp(&other.value)
+            .then(other.summon.cmp(&self.summon))
+            .then(self.id.cmp(&other.id))
+    }
+}
+
+impl PartialOrd for Initiative {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Default, Clone, Hash, PartialEq, Eq)]
 pub struct EnemyFocusInfo {
-    hex: Hex,
-    initiative: Initiative,
+    pub hex: Hex,
+    pub initiative: Initiative,
+    pub invisible: bool,
+}
+
+// The enemy a monster focuses on and the closest hex it can attack it from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Focus {
+    pub target: Hex,
+    pub from: Hex,
+    pub cost: u32,
+    pub initiative: Initiative,
+    // Whether it was found without moving through negative hexes, the monster moves the same way
+    pub negative_is_obstacle: bool,
 }
 
 // Inputs:  HexPosition
@@ -28,136 +83,438 @@ pub struct EnemyFocusInfo {
 //          Movement Kind       (to determine which negative hexes are treated as obstacles)
 //                              (to determine whether movement can end in negative hex)
 //                              (TODO: Teleport?)
-//          Map                 (empty, negative, obstacle, difficult terrain, figure; TODO: Icy Terrain?)
-//          Enemy Focus Info    (Position, Initiative, Summon?)
-//                              (Prefiltered for Inivisble)
-fn focus(hex: Hex, team: Team, range: u32, conditions: Conditions, movement_kind: MovementKind, map: HexGrid, enemies: Vec<EnemyFocusInfo>) -> Option<Hex> {
+//          Map                 (empty, negative, obstacle, difficult terrain, figure, wall; TODO: Icy Terrain?)
+//          Enemy Focus Info    (Position, Initiative, Summon?, Invisible)
+pub fn focus(
+    hex: Hex,
+    team: Team,
+    range: u32,
+    conditions: &Conditions,
+    movement_kind: MovementKind,
+    map: &MovementMap,
+    enemies: &[EnemyFocusInfo],
+) -> Option<Focus> {
     // Adjust range for certain circumstances
     let range = if conditions.has(ConditionKind::Disarm) {
         0
     } else {
         range
-    }.max(1);
-    
-    let mut foci_move: HashMap<EnemyFocusInfo, Vec<Hex>> = HashMap::new();
+    }
+    .max(1);
 
-    for enemy in enemies {
-        // Retrieve hexes to move to that allow "attack" on enemy
-        let move_to = hex.rings(1..range); // TODO: exclusive range?
+    // Invisible enemies can not be focused at all
+    let enemies: Vec<&EnemyFocusInfo> = enemies.iter().filter(|enemy| !enemy.invisible).collect();
 
-        foci_move.insert(enemy, move_to.flatten().collect());
+    // Negative hexes are only moved through if there is no other way to find a focus
+    let foci = {
+        let foci = find_foci(hex, team, range, movement_kind, map, &enemies, true);
+        if foci.is_empty() {
+            find_foci(hex, team, range, movement_kind, map, &enemies, false)
+        } else {
+            foci
+        }
+    };
+    if foci.is_empty() {
+        return None;
     }
 
-    //TODO: Determine hex cost on the grid
+    let foci = foci_tiebreaker_range(foci, hex, map);
+    let foci = foci_tiebreaker_initiative(foci);
 
-    //TODO: pathfind keeping the lowest one for that target
-    //TODO: Already consider disadvantage on range attack here?
+    Some(foci_tiebreaker_final(foci))
+}
+
+// All enemies that can be attacked with the fewest movement, together with the hex to attack from
+fn find_foci(
+    hex: Hex,
+    team: Team,
+    range: u32,
+    movement_kind: MovementKind,
+    map: &MovementMap,
+    enemies: &[&EnemyFocusInfo],
+    negative_is_obstacle: bool,
+) -> Vec<Focus> {
+    let foci: Vec<Focus> = enemies
+        .iter()
+        .filter_map(|enemy| {
+            enemy
+                .hex
+                .range(range)
+                .filter(|from| *from != enemy.hex && map.line_of_sight(*from, enemy.hex))
+                .filter_map(|from| {
+                    let path =
+                        map.find_path(hex, from, team, movement_kind, negative_is_obstacle)?;
+
+                    Some(Focus {
+                        target: enemy.hex,
+                        from,
+                        cost: path.cost,
+                        initiative: enemy.initiative,
+                        negative_is_obstacle,
+                    })
+                })
+                .min_by_key(|focus| focus.cost)
+        })
+        .collect();
 
-    //TODO: Filter foci_move my lowest movement cost
-    
-    //TODO: Resolve any ties
-    let focus = Hex::ZERO;
+    let Some(min_cost) = foci.iter().map(|focus| focus.cost).min() else {
+        return foci;
+    };
 
-    Some(focus)
+    foci.into_iter()
+        .filter(|focus| focus.cost == min_cost)
+        .collect()
 }
 
-//TODO: If no focus can be found, no longer consider negative hexes obstacles (if not flying movement)
+// Proximity counts hexes around walls, but ignores anything else in between
+fn foci_tiebreaker_range(foci: Vec<Focus>, origin: Hex, map: &MovementMap) -> Vec<Focus> {
+    let proximity = |focus: &Focus| map.proximity(origin, focus.target).unwrap_or(u32::MAX);
+    let min_range = foci.iter().map(proximity).min().unwrap();
+
+    foci.into_iter()
+        .filter(|focus| proximity(focus) == min_range)
+        .collect()
+}
+
+fn foci_tiebreaker_initiative(mut foci: Vec<Focus>) -> Vec<Focus> {
+    foci.sort_by_key(|focus| focus.initiative);
+
+    let first_initiative = foci[0].initiative;
+    foci.into_iter()
+        .filter(|focus| focus.initiative == first_initiative)
+        .collect()
+}
 
-//After Focus found
-//TODO: Determine target movement hex for that focus, based on movement, etc.
+// TODO: Any remaining tie is up to the players, for now the first one is taken
+fn foci_tiebreaker_final(foci: Vec<Focus>) -> Focus {
+    foci[0].clone()
+}
 
-fn focus_negative(hex: Hex, range: usize, movement_kind: MovementKind) -> Option<Hex> {
+// What a monster does on its turn, range 0 is melee and 0 targets means it does not attack
+// Its shield lasts until the end of the round, pierce applies to each of its attacks
+#[derive(Debug, Default, Clone, Copy, Reflect)]
+pub struct MonsterAbility {
+    pub movement: u32,
+    pub movement_kind: MovementKind,
+    pub range: u32,
+    pub targets: usize,
+    pub pierce: usize,
+    pub shield: usize,
+}
 
+// Where the monster ends its movement and which enemies it attacks from there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonsterTurn {
+    pub focus: Option<Focus>,
+    pub destination: Hex,
+    pub targets: Vec<Hex>,
 }
 
-// Inputs:    Attack Range (if any; otherwise assume range 1)
-//            Movement points
-//            Movement kind
-//            HexPosition
+// Inputs:    HexPosition
+//            Team
+//            Monster Ability     (movement, movement kind, attack range and targets)
+//            Conditions          (immobilize prevents movement; disarm prevents the attack)
+//            Map
+//            Enemy Focus Info
 // Outputs:   Hex to use the ability from (current if no movement)
 //            Hexes that are targeted (if any)
-fn ai(movement: usize, range: usize, targets: usize) -> (Hex, Vec<Hex>) {
-    let mut commands = vec![];
+pub fn ai(
+    hex: Hex,
+    team: Team,
+    ability: &MonsterAbility,
+    conditions: &Conditions,
+    map: &MovementMap,
+    enemies: &[EnemyFocusInfo],
+) -> MonsterTurn {
+    let Some(focus) = focus(
+        hex,
+        team,
+        ability.range,
+        conditions,
+        ability.movement_kind,
+        map,
+        enemies,
+    ) else {
+        return MonsterTurn {
+            focus: None,
+            destination: hex,
+            targets: vec![],
+        };
+    };
 
-    /* TODO: Ordered by initiative */
-    let figures: Vec<Entity> = vec![];
-    /* TODO: Determine focus based on least movement used given range of the attack */
-    let focus = figures
-        .iter()
-        .map(|figure| {
-            /* TODO: Get area of hexes from enemy position within range */
+    // A disarmed monster still moves towards its focus as if it had a melee attack
+    let (range, targets) = if conditions.has(ConditionKind::Disarm) {
+        (1, 0)
+    } else {
+        (ability.range.max(1), ability.targets)
+    };
+    let enemies: Vec<&EnemyFocusInfo> = enemies.iter().filter(|enemy| !enemy.invisible).collect();
 
-            /* TODO: Filter out occupied hexes, but consider always flying on monster */
+    // The monster itself is no longer in the way once it moves
+    let mut map = map.clone();
+    map.remove_figure(&hex);
 
-            /* TODO: Calculate movement necessary using pathfinding */
+    let movement = if conditions.has(ConditionKind::Immobilize) {
+        0
+    } else {
+        ability.movement
+    };
 
-            /* TODO: Take minimum */
+    let attacks = |from: Hex, enemy: Hex| {
+        from != enemy && from.unsigned_distance_to(enemy) <= range && map.line_of_sight(from, enemy)
+    };
+    // Ranged attacks against adjacent enemies have disadvantage
+    let disadvantage =
+        |from: Hex, enemy: Hex| ability.range > 1 && from.unsigned_distance_to(enemy) == 1;
+    // Anyone else in reach is attacked as well, preferably without disadvantage
+    let extra_targets = |from: Hex| {
+        let mut extra_targets: Vec<&EnemyFocusInfo> = enemies
+            .iter()
+            .filter(|enemy| enemy.hex != focus.target && attacks(from, enemy.hex))
+            .copied()
+            .collect();
+        extra_targets.sort_by_key(|enemy| (disadvantage(from, enemy.hex), enemy.initiative));
+        extra_targets.truncate(targets.saturating_sub(1));
+
+        extra_targets
+    };
 
-            figure
+    let destination = hex
+        .range(movement)
+        .filter_map(|destination| {
+            let path = map.find_path(
+                hex,
+                destination,
+                team,
+                ability.movement_kind,
+                focus.negative_is_obstacle,
+            )?;
+            (path.cost <= movement).then_some((destination, path.cost))
+        })
+        .min_by_key(|(destination, cost)| {
+            let attacks_focus = attacks(*destination, focus.target);
+            let extra_targets = extra_targets(*destination);
+            let extra_disadvantage = extra_targets
+                .iter()
+                .filter(|enemy| disadvantage(*destination, enemy.hex))
+                .count();
+
+            // If the focus can not be attacked this turn, get as close as possible for the next one
+            let remaining_cost = if attacks_focus {
+                0
+            } else {
+                attack_cost(
+                    *destination,
+                    team,
+                    range,
+                    ability.movement_kind,
+                    &map,
+                    &focus,
+                )
+            };
+
+            (
+                !attacks_focus,
+                attacks_focus && disadvantage(*destination, focus.target),
+                Reverse(extra_targets.len()),
+                extra_disadvantage,
+                remaining_cost,
+                *cost,
+                (destination.x, destination.y),
+            )
         })
-        .min();
+        .map(|(destination, _)| destination)
+        .unwrap_or(hex);
 
-    /* Only move if there is a focus */
-    if let Some(focus) = focus {
-        if movement > 0 {
-            /* TODO: Determine all valid hexes from where focus can be attack given movement and range */
+    let mut attacked: Vec<Hex> = vec![];
+    if targets > 0 && attacks(destination, focus.target) {
+        attacked.push(focus.target);
+        attacked.extend(extra_targets(destination).iter().map(|enemy| enemy.hex));
+    }
 
-            /* TODO: If attack is multi target it gets a bit more complicated */
-            if targets > 1 {
-            } else {
-                /* TODO: Just take the hex with lowest movement */
-            }
-        } else {
-            /* TODO: In this case I might want to do the reverse and go through the hexes I can attack around me */
-        }
+    MonsterTurn {
+        focus: Some(focus),
+        destination,
+        targets: attacked,
     }
+}
+
+// Fewest movement needed from a hex to any hex the focus can be attacked from
+fn attack_cost(
+    hex: Hex,
+    team: Team,
+    range: u32,
+    movement_kind: MovementKind,
+    map: &MovementMap,
+    focus: &Focus,
+) -> u32 {
+    focus
+        .target
+        .range(range)
+        .filter(|from| *from != focus.target && map.line_of_sight(*from, focus.target))
+        .filter_map(|from| {
+            map.find_path(hex, from, team, movement_kind, focus.negative_is_obstacle)
+        })
+        .map(|path| path.cost)
+        .min()
+        .unwrap_or(u32::MAX)
+}
 
-    commands
+/// The initiative a figure is focused by, figures that did not get one this round act first
+pub fn figure_initiative(world: &World, entity: Entity) -> Option<Initiative> {
+    let id = world.get::<FigureId>(entity)?;
+    let initiative = world
+        .get_resource::<Initiatives>()
+        .and_then(|initiatives| initiatives.get(id))
+        .unwrap_or_default();
+
+    Some(Initiative::new(
+        initiative as usize,
+        false,
+        id.id() as usize,
+    ))
 }
 
+/// Run the AI for a monster on the current state of the world
+pub fn monster_turn(
+    world: &World,
+    entity: Entity,
+    ability: &MonsterAbility,
+) -> Option<MonsterTurn> {
+    let hex_grid = world.get::<HexGrid>(world.get::<Parent>(entity)?.get())?;
+    let hex = world.get::<HexPosition>(entity)?.hex();
+    let team = *world.get::<Team>(entity)?;
+    let conditions = world.get::<Conditions>(entity)?;
+
+    let enemies: Vec<EnemyFocusInfo> = hex_grid
+        .iter(&HexLayer::Figure)
+        .filter_map(|(hex, enemy)| {
+            let enemy_team = world.get::<Team>(enemy)?;
+            if enemy_team.is_ally_of(&team) {
+                return None;
+            }
 
-fn ai_new() {
-    let foci = {
-        let foci = find_foci(true);
-        if foci.is_empty() {
-            find_foci(false)
-        } else {
-            foci
-        }
-    };
+            Some(EnemyFocusInfo {
+                hex,
+                initiative: figure_initiative(world, enemy)?,
+                invisible: world
+                    .get::<Conditions>(enemy)
+                    .is_some_and(|conditions| conditions.has(ConditionKind::Invisible)),
+            })
+        })
+        .collect();
+
+    let map = MovementMap::from_hex_grid(hex_grid, world);
+
+    Some(ai(hex, team, ability, conditions, &map, &enemies))
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct Focus {
-    from: Hex,
-    target: Hex,
+/* Lets the AI decide how a monster moves and whom it attacks */
+/* The decision is queued up as follow-ups, so undo only has to undo those */
+#[derive(Debug, Clone, Reflect)]
+pub struct MonsterActionCommand {
+    entity: Entity,
+    ability: MonsterAbility,
+    attack: usize,
 }
 
-fn find_foci(negative_is_obstacle: bool) -> Vec<Focus> {
-    vec![]   
+impl MonsterActionCommand {
+    pub fn new(entity: Entity, ability: MonsterAbility, attack: usize) -> Self {
+        Self {
+            entity,
+            ability,
+            attack,
+        }
+    }
 }
 
-fn foci_tiebreaker_range(foci: Vec<Focus>, origin: Hex) -> Vec<Focus> {
-    let min_range = foci.iter().min_by(|x, y| {
-        x.target.unsigned_distance_to(origin).cmp(&y.target.unsigned_distance_to(origin))
-    }).unwrap().target.unsigned_distance_to(origin);
+impl ScenarioCommandTrait for MonsterActionCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let mut commands = vec![];
+        if self.ability.shield > 0 {
+            commands.push(
+                AddBonusCommand::new(self.entity, BonusDuration::Round)
+                    .with_shield(Shield(self.ability.shield))
+                    .into(),
+            );
+        }
 
-    foci.into_iter().filter(|f| {
-        f.target.unsigned_distance_to(origin) == min_range
-    }).collect()
-}
+        let Some(monster_turn) = monster_turn(world, self.entity, &self.ability) else {
+            return ScenarionCommandExecuteResult::Done(commands);
+        };
+        let Some(focus) = &monster_turn.focus else {
+            debug!("{} has no focus", self.entity);
+            return ScenarionCommandExecuteResult::Done(commands);
+        };
+        debug!("{} focuses on {:?}", self.entity, focus.target);
+
+        if world.get::<HexPosition>(self.entity).unwrap().hex() != monster_turn.destination {
+            commands.push(
+                MoveCommand::new(self.entity, monster_turn.destination, self.ability.movement)
+                    .with_kind(self.ability.movement_kind)
+                    .with_negative_is_obstacle(focus.negative_is_obstacle)
+                    .into(),
+            );
+        }
+
+        let hex_grid = world
+            .get::<HexGrid>(world.get::<Parent>(self.entity).unwrap().get())
+            .unwrap();
+        for target in monster_turn.targets {
+            let target = hex_grid.get(&target, &HexLayer::Figure).unwrap();
+            let attack = Attack::new(target, self.attack)
+                .with_range(self.ability.range)
+                .with_pierce(self.ability.pierce);
+            commands.push(AttackCommand::new(self.entity, attack).into());
+        }
+
+        ScenarionCommandExecuteResult::Done(commands)
+    }
+
+    fn undo(self, _world: &mut World) -> ScenarioCommand {
+        self.into()
+    }
 
-fn foci_tiebreaker_initiative(foci: Vec<Focus>, figure_id: HashMap<Hex, u32>, initiative: HashMap<u32, u32>) -> Vec<Focus> {
-    foci.sort_by(|f1, f2| {
-        let f1_initiative = initiative.get(figure_id.get(&f1.target).unwrap()).unwrap();
-        let f2_initiative = initiative.get(figure_id.get(&f2.target).unwrap()).unwrap();
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
 
-        f1_initiative.cmp(f2_initiative)
-    });
+/* The ability card each monster type plays this round, together with its attack value */
+#[derive(Debug, Default, Clone, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct MonsterAbilities(HashMap<FigureId, (MonsterAbility, usize)>);
 
+impl MonsterAbilities {
+    pub fn get(&self, id: &FigureId) -> Option<(MonsterAbility, usize)> {
+        self.0.get(id).copied()
+    }
 
+    pub fn set(&mut self, id: FigureId, ability: MonsterAbility, attack: usize) {
+        self.0.insert(id, (ability, attack));
+    }
 }
 
-fn foci_tiebreaker_final(foci: Vec<Focus>) -> Focus {
-    foci[0]
-}
\ No newline at end of file
+/* Monsters act on their own, their turn ends once their ability is resolved */
+pub fn take_monster_turns(
+    mut command_queue: ResMut<ScenarioCommandQueue>,
+    mut start_of_turn: EventReader<StartOfTurn>,
+    monster_abilities: Res<MonsterAbilities>,
+    figures: Query<(&FigureId, &Team, &Conditions)>,
+) {
+    for event in start_of_turn.read() {
+        let Ok((id, Team::Monster, conditions)) = figures.get(event.entity) else {
+            continue;
+        };
+
+        /* A stunned monster skips its ability, its turn still ends so the stun can expire */
+        let mut commands = vec![];
+        if conditions.has(ConditionKind::Stun) {
+            debug!("{} is stunned", event.entity);
+        } else if let Some((ability, attack)) = monster_abilities.get(id) {
+            commands.push(MonsterActionCommand::new(event.entity, ability, attack).into());
+        }
+        commands.push(EndTurnCommand::new(event.entity).into());
+        command_queue.queue_transaction(commands);
+    }
+}
@@ -0,0 +1,752 @@
+// Monster focus and movement puzzles
+//
+// Each puzzle is a hex map in the notation of scenario::notation, a hexagon around the
+// monster whose turn it is, which is always M0 in the middle at (0, 4). Other figures only
+// get an initiative and conditions on top of the map. Hexes are given as (x, y) of the map.
+
+use bevy::{ecs::system::RunSystemOnce, prelude::*, utils::HashMap};
+use hexx::Hex;
+
+use crate::{
+    figure::{
+        ai::{monster_turn, take_monster_turns, MonsterAbilities, MonsterAbility},
+        bonus::{calculated_shield, Shield},
+        condition::{ConditionKind, Conditions},
+        health::Health,
+        movement::MovementKind,
+        FigureId, FigureInstance, Initiatives,
+    },
+    game::{StartOfTurn, TurnOrder},
+    scenario::{
+        command::{ScenarioCommand, ScenarioCommandQueue},
+        notation::{parse_hex_map, FIGURE_HEALTH},
+    },
+};
+
+const MELEE: MonsterAbility = MonsterAbility {
+    movement: 2,
+    movement_kind: MovementKind::Default,
+    range: 0,
+    targets: 1,
+    pierce: 0,
+    shield: 0,
+};
+
+struct Puzzle {
+    world: World,
+    figures: HashMap<(FigureId, FigureInstance), Entity>,
+}
+
+impl Puzzle {
+    fn new(map: &str) -> Self {
+        let mut world = World::new();
+        let figures = parse_hex_map(map).unwrap().spawn(&mut world.commands());
+        world.flush();
+        world.init_resource::<Initiatives>();
+
+        Self { world, figures }
+    }
+
+    fn figure(&self, id: u32) -> Entity {
+        self.figures[&(FigureId::new(id), FigureInstance::new(0))]
+    }
+
+    fn with_initiative(mut self, id: u32, initiative: u8) -> Self {
+        self.world
+            .resource_mut::<Initiatives>()
+            .set(FigureId::new(id), initiative);
+        self
+    }
+
+    fn with_condition(mut self, id: u32, condition: ConditionKind) -> Self {
+        let entity = self.figure(id);
+        self.world
+            .get_mut::<Conditions>(entity)
+            .unwrap()
+            .add_condition(condition);
+        self
+    }
+
+    fn assert(
+        &self,
+        ability: MonsterAbility,
+        focus: Option<(i32, i32)>,
+        destination: (i32, i32),
+        targets: &[(i32, i32)],
+    ) {
+        let turn = monster_turn(&self.world, self.figure(0), &ability).unwrap();
+        let hex = |(x, y): (i32, i32)| Hex::new(x, y);
+
+        assert_eq!(
+            turn.focus.map(|focus| focus.target),
+            focus.map(hex),
+            "focus"
+        );
+        assert_eq!(turn.destination, hex(destination), "destination");
+        assert_eq!(
+            turn.targets,
+            targets.iter().copied().map(hex).collect::<Vec<_>>(),
+            "targets"
+        );
+    }
+}
+
+fn ranged(range: u32) -> MonsterAbility {
+    MonsterAbility { range, ..MELEE }
+}
+
+fn movement(movement: u32) -> MonsterAbility {
+    MonsterAbility { movement, ..MELEE }
+}
+
+#[test]
+fn attacks_adjacent_enemy_without_moving() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  P1  .   .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(MELEE, Some((1, 4)), (0, 4), &[(1, 4)]);
+}
+
+#[test]
+fn focuses_enemy_needing_fewest_movement() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   P2  .   M0  .   .   P1  .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_initiative(2, 50)
+    .assert(MELEE, Some((-2, 4)), (-1, 4), &[(-2, 4)]);
+}
+
+#[test]
+fn equal_movement_focuses_closest_enemy() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   P2  .   M0  .   .   P1  .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_initiative(2, 50)
+    .assert(ranged(3), Some((-2, 4)), (0, 4), &[(-2, 4)]);
+}
+
+#[test]
+fn equal_movement_and_proximity_focuses_lowest_initiative() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   P2  .   M0  .   P1  .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .with_initiative(2, 20)
+    .assert(MELEE, Some((-2, 4)), (-1, 4), &[(-2, 4)]);
+}
+
+#[test]
+fn allies_of_the_characters_are_enemies_too() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   A2  .   M0  .   P1  .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .with_initiative(2, 5)
+    .assert(MELEE, Some((-2, 4)), (-1, 4), &[(-2, 4)]);
+}
+
+#[test]
+fn invisible_enemies_are_ignored() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   P1  M0  .   P2  .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_initiative(2, 50)
+    .with_condition(1, ConditionKind::Invisible)
+    .assert(movement(1), Some((2, 4)), (1, 4), &[(2, 4)]);
+}
+
+#[test]
+fn no_focus_without_visible_enemies() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   P1  .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_condition(1, ConditionKind::Invisible)
+    .assert(MELEE, None, (0, 4), &[]);
+}
+
+#[test]
+fn no_focus_without_enemies() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  M1  .   .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .assert(MELEE, None, (0, 4), &[]);
+}
+
+#[test]
+fn moves_around_obstacles() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   O   O   .   .   .
+        .   .   .   .   M0  O   P1  .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(movement(3), Some((2, 4)), (1, 5), &[(2, 4)]);
+}
+
+#[test]
+fn difficult_terrain_changes_the_focus() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   D   .   .   .
+        .   .   P2  .   M0  D   P1  .   .
+          .   .   .   .   D   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_initiative(2, 50)
+    .assert(MELEE, Some((-2, 4)), (-1, 4), &[(-2, 4)]);
+}
+
+#[test]
+fn walls_change_the_focus() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   P2  .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   .   .   .
+          .   .   .   .   .   #   .   .
+            .   .   .   #   #   .   .
+              .   .   .   .   P1  .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_initiative(2, 50)
+    .assert(MELEE, Some((3, 1)), (2, 2), &[(3, 1)]);
+}
+
+#[test]
+fn moves_through_allied_monsters() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   O   .   .   .
+        .   .   .   .   M0  M1  .   P2  .
+          .   .   .   .   O   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(2, 50)
+    .assert(MELEE, Some((3, 4)), (2, 4), &[(3, 4)]);
+}
+
+#[test]
+fn can_not_end_on_allied_monsters() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   O   .   .
+        .   .   .   .   M0  M1  P2  .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(2, 50)
+    .assert(MELEE, Some((2, 4)), (1, 5), &[(2, 4)]);
+}
+
+#[test]
+fn can_not_move_through_enemies() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   O   O   .   .   .
+        .   .   .   O   M0  P1  .   P2  .
+          .   .   .   O   O   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .with_initiative(2, 10)
+    .with_condition(1, ConditionKind::Invisible)
+    .assert(movement(3), None, (0, 4), &[]);
+}
+
+#[test]
+fn gets_as_close_as_possible_when_out_of_reach() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   .   .   P1
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(MELEE, Some((4, 4)), (2, 4), &[]);
+}
+
+#[test]
+fn avoids_traps() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   O   .   .   .
+        .   .   .   .   M0  T   P1  .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(MELEE, Some((2, 4)), (1, 5), &[(2, 4)]);
+}
+
+#[test]
+fn avoids_hazardous_terrain() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  H   P1  .   .
+          .   .   .   .   .   O   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(MELEE, Some((2, 4)), (2, 3), &[(2, 4)]);
+}
+
+#[test]
+fn prefers_focus_without_traps_in_the_way() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   T   .   .
+        .   P2  .   .   M0  T   P1  .   .
+          .   .   .   .   .   T   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_initiative(2, 50)
+    .assert(MELEE, Some((-3, 4)), (-2, 4), &[(-3, 4)]);
+}
+
+#[test]
+fn moves_through_traps_if_there_is_no_other_way() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   T   T   .   .   .
+        .   .   .   T   M0  T   P1  .   .
+          .   .   .   T   T   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(MELEE, Some((2, 4)), (1, 4), &[(2, 4)]);
+}
+
+#[test]
+fn ranged_attacks_from_where_it_stands() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   .   P1  .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(ranged(3), Some((3, 4)), (0, 4), &[(3, 4)]);
+}
+
+#[test]
+fn ranged_steps_away_to_avoid_disadvantage() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   O   .   .   .   .
+        .   .   .   .   M0  P1  .   .   .
+          .   .   .   O   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(ranged(2), Some((1, 4)), (-1, 4), &[(1, 4)]);
+}
+
+#[test]
+fn ranged_needs_line_of_sight() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   P2  .   .   M0  .   #   P1  .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_initiative(2, 50)
+    .assert(ranged(3), Some((-3, 4)), (0, 4), &[(-3, 4)]);
+}
+
+#[test]
+fn ranged_moves_into_range() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   .   .   P1
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(ranged(3), Some((4, 4)), (1, 4), &[(4, 4)]);
+}
+
+#[test]
+fn moves_to_attack_additional_targets() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   P2  .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   P1  .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_initiative(2, 50)
+    .assert(
+        MonsterAbility {
+            targets: 2,
+            ..MELEE
+        },
+        Some((2, 4)),
+        (2, 3),
+        &[(2, 4), (2, 2)],
+    );
+}
+
+#[test]
+fn focus_comes_before_additional_targets() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   P2  .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   P1  .   .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 10)
+    .with_initiative(2, 50)
+    .assert(
+        MonsterAbility {
+            movement: 1,
+            targets: 2,
+            ..MELEE
+        },
+        Some((2, 4)),
+        (1, 4),
+        &[(2, 4)],
+    );
+}
+
+#[test]
+fn immobilized_still_attacks_in_range() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   .   P1  .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .with_condition(0, ConditionKind::Immobilize)
+    .assert(ranged(3), Some((3, 4)), (0, 4), &[(3, 4)]);
+}
+
+#[test]
+fn immobilized_does_not_move() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   .   P1  .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .with_condition(0, ConditionKind::Immobilize)
+    .assert(MELEE, Some((3, 4)), (0, 4), &[]);
+}
+
+#[test]
+fn disarmed_moves_like_melee_without_attacking() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   .   .   .   .   .
+        .   .   .   .   M0  .   .   P1  .
+          .   .   .   .   .   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .with_condition(0, ConditionKind::Disarm)
+    .assert(ranged(3), Some((3, 4)), (2, 4), &[]);
+}
+
+#[test]
+fn flying_ignores_obstacles() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   O   O   .   .   .
+        .   .   .   O   M0  O   .   P1  .
+          .   .   .   O   O   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(
+        MonsterAbility {
+            movement_kind: MovementKind::Fly,
+            ..MELEE
+        },
+        Some((3, 4)),
+        (2, 4),
+        &[(3, 4)],
+    );
+}
+
+#[test]
+fn jumping_ignores_obstacles() {
+    Puzzle::new(
+        "
+                .   .   .   .   .
+              .   .   .   .   .   .
+            .   .   .   .   .   .   .
+          .   .   .   O   O   .   .   .
+        .   .   .   O   M0  O   .   P1  .
+          .   .   .   O   O   .   .   .
+            .   .   .   .   .   .   .
+              .   .   .   .   .   .
+                .   .   .   .   .",
+    )
+    .with_initiative(1, 50)
+    .assert(
+        MonsterAbility {
+            movement_kind: MovementKind::Jump,
+            ..MELEE
+        },
+        Some((3, 4)),
+        (2, 4),
+        &[(3, 4)],
+    );
+}
+
+/* Plays the turn of M0 with an attack of 2, characters always suffer the damage */
+fn take_turn(puzzle: &mut Puzzle, ability: MonsterAbility) {
+    let entity = puzzle.figure(0);
+    let world = &mut puzzle.world;
+    let mut monster_abilities = MonsterAbilities::default();
+    monster_abilities.set(FigureId::new(0), ability, 2);
+    world.insert_resource(monster_abilities);
+    world.init_resource::<ScenarioCommandQueue>();
+    world.init_resource::<TurnOrder>();
+    world.init_resource::<Events<StartOfTurn>>();
+
+    world.send_event(StartOfTurn { entity });
+    world.run_system_once(take_monster_turns).unwrap();
+    world.resource_scope(|world, mut command_queue: Mut<ScenarioCommandQueue>| {
+        command_queue.execute_all(world);
+        while command_queue.input_request().is_some() {
+            command_queue.respond(world, 0);
+        }
+    });
+}
+
+fn acted(puzzle: &Puzzle) -> bool {
+    puzzle
+        .world
+        .resource::<ScenarioCommandQueue>()
+        .history()
+        .any(|command| matches!(command, ScenarioCommand::MonsterActionCommand(_)))
+}
+
+#[test]
+fn stunned_does_not_act() {
+    let mut puzzle = Puzzle::new("M0  P1");
+    take_turn(&mut puzzle, MELEE);
+    assert!(acted(&puzzle));
+
+    let mut puzzle = Puzzle::new("M0  P1").with_condition(0, ConditionKind::Stun);
+    take_turn(&mut puzzle, MELEE);
+    assert!(!acted(&puzzle));
+}
+
+#[test]
+fn shields_itself_and_pierces() {
+    let mut puzzle = Puzzle::new("M0  P1");
+    let (monster, player) = (puzzle.figure(0), puzzle.figure(1));
+    puzzle.world.entity_mut(player).insert(Shield(2));
+
+    take_turn(
+        &mut puzzle,
+        MonsterAbility {
+            pierce: 1,
+            shield: 1,
+            ..MELEE
+        },
+    );
+
+    assert_eq!(calculated_shield(&puzzle.world, monster), 1);
+    assert_eq!(
+        puzzle.world.get::<Health>(player).unwrap().current(),
+        FIGURE_HEALTH - 1
+    );
+}
@@ -1,10 +1,25 @@
-use bevy::prelude::*;
+use bevy::{ecs::entity::EntityMapper, prelude::*};
 
-use crate::scenario::command::{
-    ScenarioCommand, ScenarioCommandQueue, ScenarioCommandTrait, ScenarionCommandExecuteResult,
+use crate::scenario::{
+    command::{ScenarioCommand, ScenarioCommandTrait, ScenarionCommandExecuteResult},
+    element::InfuseElementCommand,
+    input::InputRequest,
+    map::{HexGrid, HexLayer, HexPosition},
+    pathfinding::MovementMap,
 };
 
-use super::{condition::Conditions, health::SufferDamageCommand, modifier::RollModifierCommand};
+use super::{
+    ai::figure_initiative,
+    bonus::{calculated_retaliate, calculated_shield},
+    condition::{AddConditionCommand, ConditionKind, Conditions},
+    health::{HealCommand, Health, SufferDamageCommand},
+    modifier::{
+        deck::{AddModifierCardCommand, ModifierCard},
+        modifier_source, Modifier, ModifierEffect, ModifierTrayColumn, RollModifierCommand,
+    },
+    movement::{ForcedMovementCommand, ForcedMovementKind},
+    Team,
+};
 
 /* Lets try having this be a component. Not sure if that is a good idea */
 
@@ -12,34 +27,198 @@ use super::{condition::Conditions, health::SufferDamageCommand, modifier::RollMo
 pub struct Attack {
     target: Entity,
     value: usize,
+    /* Ignores this much of the shield of the target */
+    pierce: usize,
+    /* Anything beyond 1 is a ranged attack */
+    range: u32,
 }
 
 impl Attack {
     pub fn new(target: Entity, value: usize) -> Self {
-        Self { target, value }
+        Self {
+            target,
+            value,
+            pierce: 0,
+            range: 1,
+        }
+    }
+
+    pub fn with_pierce(self, pierce: usize) -> Self {
+        Self { pierce, ..self }
+    }
+
+    pub fn with_range(self, range: u32) -> Self {
+        Self { range, ..self }
+    }
+
+    pub fn is_ranged(&self) -> bool {
+        self.range > 1
+    }
+}
+
+/* Advantage and disadvantage cancel each other out, neither stacks */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum AttackAdvantage {
+    Normal,
+    Advantage,
+    Disadvantage,
+}
+
+impl AttackAdvantage {
+    pub fn draws(&self) -> usize {
+        match self {
+            AttackAdvantage::Normal => 1,
+            AttackAdvantage::Advantage | AttackAdvantage::Disadvantage => 2,
+        }
+    }
+}
+
+/// Strengthen gives advantage, Muddle and ranged attacks against adjacent targets disadvantage
+pub fn attack_advantage(world: &World, source: Entity, attack: &Attack) -> AttackAdvantage {
+    let conditions = world.get::<Conditions>(source);
+    let has = |condition| conditions.is_some_and(|conditions| conditions.has(condition));
+    let is_adjacent = match (
+        world.get::<HexPosition>(source),
+        world.get::<HexPosition>(attack.target),
+    ) {
+        (Some(source), Some(target)) => source.hex().unsigned_distance_to(target.hex()) == 1,
+        _ => false,
+    };
+
+    let advantage = has(ConditionKind::Strengthen);
+    let disadvantage = has(ConditionKind::Muddle) || (attack.is_ranged() && is_adjacent);
+    match (advantage, disadvantage) {
+        (true, false) => AttackAdvantage::Advantage,
+        (false, true) => AttackAdvantage::Disadvantage,
+        _ => AttackAdvantage::Normal,
     }
 }
 
+/// The drawn modifiers that count, the better draw with advantage and the worse one with disadvantage
+/// A draw is a card together with the rolling modifiers before it, with disadvantage those are ignored
+pub fn pick_modifiers(
+    advantage: AttackAdvantage,
+    drawn: &[Modifier],
+    damage: usize,
+) -> Vec<Modifier> {
+    let mut draws = drawn.split_inclusive(|modifier| !modifier.is_rolling());
+    let result = |draw: &&[Modifier]| Modifier::apply_all(draw, damage);
+    let picked = match advantage {
+        AttackAdvantage::Normal => draws.next(),
+        AttackAdvantage::Advantage => draws.rev().max_by_key(result),
+        AttackAdvantage::Disadvantage => draws
+            .map(|draw| &draw[draw.len() - 1..])
+            .rev()
+            .min_by_key(result),
+    };
+
+    picked.unwrap().to_vec()
+}
+
 #[derive(Debug, Clone, Reflect)]
 pub struct AttackCommand {
     source: Entity,
     attack: Attack,
+    /* Earlier targets of the same ability, an extra target can not be one of them */
+    targeted: Vec<Entity>,
 }
 
 impl AttackCommand {
     pub fn new(source: Entity, attack: Attack) -> Self {
-        Self { source, attack }
+        Self {
+            source,
+            attack,
+            targeted: Default::default(),
+        }
     }
+
+    pub fn with_targeted(self, targeted: Vec<Entity>) -> Self {
+        Self { targeted, ..self }
+    }
+}
+
+/// The enemy in range and sight that was not targeted yet, picked the way a focus is
+/// None of them needs any movement, so proximity decides before initiative
+pub fn extra_target(
+    world: &World,
+    source: Entity,
+    attack: &Attack,
+    targeted: &[Entity],
+) -> Option<Entity> {
+    let hex_grid = world
+        .get::<Parent>(source)
+        .and_then(|parent| world.get::<HexGrid>(parent.get()))?;
+    let origin = world.get::<HexPosition>(source)?.hex();
+    let team = world.get::<Team>(source)?;
+    let map = MovementMap::from_hex_grid(hex_grid, world);
+
+    hex_grid
+        .iter(&HexLayer::Figure)
+        .filter(|(_, entity)| *entity != attack.target && !targeted.contains(entity))
+        .filter(|(_, entity)| {
+            world
+                .get::<Team>(*entity)
+                .is_some_and(|other| !team.is_ally_of(other))
+        })
+        .filter(|(_, entity)| {
+            world
+                .get::<Health>(*entity)
+                .is_some_and(|health| !health.is_dead())
+        })
+        .filter(|(hex, _)| {
+            origin.unsigned_distance_to(*hex) <= attack.range
+                && hex_grid.line_of_sight(origin, *hex)
+        })
+        /* The coordinates only break ties, so the same target is picked every time */
+        .min_by_key(|(hex, entity)| {
+            (
+                map.proximity(origin, *hex).unwrap_or(u32::MAX),
+                figure_initiative(world, *entity),
+                hex.x,
+                hex.y,
+            )
+        })
+        .map(|(_, entity)| entity)
+}
+
+/// Whether the source can see the target, no attack can target anything without
+pub fn has_line_of_sight(world: &World, source: Entity, target: Entity) -> bool {
+    let Some(hex_grid) = world
+        .get::<Parent>(source)
+        .and_then(|parent| world.get::<HexGrid>(parent.get()))
+    else {
+        return false;
+    };
+    let (Some(source), Some(target)) = (
+        world.get::<HexPosition>(source),
+        world.get::<HexPosition>(target),
+    ) else {
+        return false;
+    };
+
+    hex_grid.line_of_sight(source.hex(), target.hex())
 }
 
 impl ScenarioCommandTrait for AttackCommand {
-    fn execute(&mut self, _world: &mut World) -> ScenarionCommandExecuteResult {
-        /* Queue up RollModifierCommand */
-        /* Queue up ApplyAttackCommand */
-        ScenarionCommandExecuteResult::Done(vec![
-            RollModifierCommand::new(self.source).into(),
-            ApplyAttackCommand::new(self.source).into(),
-        ])
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        if !has_line_of_sight(world, self.source, self.attack.target) {
+            debug!(
+                "{} can not see {} to attack it",
+                self.source, self.attack.target
+            );
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        }
+
+        let advantage = attack_advantage(world, self.source, &self.attack);
+
+        /* Queue up ApplyAttackCommand, it rolls the modifiers itself */
+        ScenarionCommandExecuteResult::Done(vec![ApplyAttackCommand::new(
+            self.source,
+            self.attack,
+            advantage,
+        )
+        .with_targeted(self.targeted.clone())
+        .into()])
     }
 
     fn undo(self, _world: &mut World) -> ScenarioCommand {
@@ -47,43 +226,108 @@ impl ScenarioCommandTrait for AttackCommand {
 
         self.into()
     }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.source = entity_mapper.map_entity(self.source);
+        self.attack.target = entity_mapper.map_entity(self.attack.target);
+        for target in &mut self.targeted {
+            *target = entity_mapper.map_entity(*target);
+        }
+    }
 }
 
 #[derive(Debug, Clone, Reflect)]
 pub struct ApplyAttackCommand {
     source: Entity,
+    attack: Attack,
+    advantage: AttackAdvantage,
+    targeted: Vec<Entity>,
+    /* The answers of the players rolling a real die, in the order they were asked */
+    columns: Vec<ModifierTrayColumn>,
+    /* Undone in reverse together with this command */
+    rolls: Vec<RollModifierCommand>,
+    /* Every modifier drawn for this attack, only the ones of a single draw count */
+    drawn: Vec<Modifier>,
+    modifiers: Vec<Modifier>,
+    /* Damage after modifiers and shield, as it was handed to SufferDamageCommand */
+    final_damage: Option<usize>,
 }
 
 impl ApplyAttackCommand {
-    pub fn new(source: Entity) -> Self {
-        Self { source }
+    pub fn new(source: Entity, attack: Attack, advantage: AttackAdvantage) -> Self {
+        Self {
+            source,
+            attack,
+            advantage,
+            targeted: Default::default(),
+            columns: Default::default(),
+            rolls: Default::default(),
+            drawn: Default::default(),
+            modifiers: Default::default(),
+            final_damage: Default::default(),
+        }
     }
-}
 
-impl ScenarioCommandTrait for ApplyAttackCommand {
-    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
-        /* Retrieve last attack command and any modifier commands from queue */
-        let queue = world.get_resource::<ScenarioCommandQueue>().unwrap();
-        let (attack, modifiers) = {
-            let history = queue.history();
-            let mut attack = None;
-            let mut modifiers = vec![];
-
-            for command in history {
-                match command {
-                    ScenarioCommand::AttackCommand(attack_command) => {
-                        attack = Some(attack_command.attack);
-                        break;
+    pub fn with_targeted(self, targeted: Vec<Entity>) -> Self {
+        Self { targeted, ..self }
+    }
+
+    #[cfg(test)]
+    pub fn drawn(&self) -> &[Modifier] {
+        &self.drawn
+    }
+
+    #[cfg(test)]
+    pub fn final_damage(&self) -> Option<usize> {
+        self.final_damage
+    }
+
+    /// Roll every modifier of this attack, a rolling modifier is followed by another roll
+    /// If a roll waits for a die the players rolled, it is all undone until they answered
+    fn roll_modifiers(&self, world: &mut World) -> Result<Vec<RollModifierCommand>, InputRequest> {
+        /* Without modifier source a single neutral modifier is drawn */
+        let mut draws = modifier_source(world, self.source)
+            .map_or(1, |modifier_source| modifier_source.draws(self.advantage));
+        let mut columns = self.columns.iter();
+        let mut rolls: Vec<RollModifierCommand> = vec![];
+
+        while draws > 0 {
+            let mut roll = RollModifierCommand::new(self.source).with_advantage(self.advantage);
+            if let Some(column) = columns.next() {
+                roll.input(*column as usize);
+            }
+
+            match roll.execute(world) {
+                ScenarionCommandExecuteResult::Pending(input_request) => {
+                    for roll in rolls.into_iter().rev() {
+                        roll.undo(world);
                     }
-                    ScenarioCommand::RollModifierCommand(roll_modifier_command) => {
-                        modifiers.push(roll_modifier_command.modifier().unwrap())
+                    return Err(input_request);
+                }
+                /* The follow-up of a rolling modifier is the next roll of the same draw */
+                ScenarionCommandExecuteResult::Done(follow_ups) => {
+                    if follow_ups.is_empty() {
+                        draws -= 1;
                     }
-                    _ => {}
                 }
             }
+            rolls.push(roll);
+        }
+
+        Ok(rolls)
+    }
+}
+
+impl ScenarioCommandTrait for ApplyAttackCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let attack = self.attack;
 
-            (attack.unwrap(), modifiers)
+        let rolls = match self.roll_modifiers(world) {
+            Ok(rolls) => rolls,
+            Err(input_request) => return ScenarionCommandExecuteResult::Pending(input_request),
         };
+        let drawn: Vec<Modifier> = rolls.iter().filter_map(|roll| roll.modifier()).collect();
+        self.rolls = rolls;
 
         /* Retrieve target entity and conditions */
         let target_conditions = world.get::<Conditions>(attack.target).unwrap();
@@ -95,25 +339,416 @@ impl ScenarioCommandTrait for ApplyAttackCommand {
         }
 
         /* Apply attack modifier */
-        /* TODO: Fix casting */
-        modifiers.iter().fold(damage as i8, |acc, x| x.apply(acc));
+        let modifiers = pick_modifiers(self.advantage, &drawn, damage);
+        damage = Modifier::modified_damage(&modifiers, damage);
+        self.drawn = drawn;
+        self.modifiers = modifiers;
 
         /* Calculate versus target shield */
-        /* TODO: Implement this */
+        let pierce = attack.pierce
+            + self
+                .modifiers
+                .iter()
+                .filter_map(|modifier| match modifier.effect {
+                    Some(ModifierEffect::Pierce(pierce)) => Some(pierce),
+                    _ => None,
+                })
+                .sum::<usize>();
+        let shield = calculated_shield(world, attack.target);
+        damage = damage.saturating_sub(shield.saturating_sub(pierce));
+        self.final_damage = Some(damage);
+        debug!(
+            "{} attacks {} for {} damage ({:?} of {:?}, shield {}, pierce {})",
+            self.source, attack.target, damage, self.modifiers, self.drawn, shield, pierce
+        );
 
         /* Queue up SufferDamageCommand */
-        /* TODO: Queue up Retaliate stuff */
+        let mut follow_ups =
+            vec![SufferDamageCommand::new(self.source, attack.target, damage).into()];
+
+        /* The effects of the modifiers follow the damage, an extra target is attacked last */
+        let (extra_targets, effects): (Vec<_>, Vec<_>) = self
+            .modifiers
+            .iter()
+            .filter_map(|modifier| modifier.effect)
+            .partition(|effect| *effect == ModifierEffect::ExtraTarget);
+        follow_ups.extend(
+            effects
+                .into_iter()
+                .filter_map(|effect| effect_follow_up(self.source, attack.target, effect)),
+        );
+
+        /* Retaliate comes after the damage, only a surviving target strikes back */
+        /* Distance 0 is within any range, so this is whether there is any retaliate at all */
+        if calculated_retaliate(world, attack.target, 0) > 0 {
+            follow_ups.push(RetaliateCommand::new(attack.target, self.source).into());
+        }
+
+        /* Each extra target is attacked on its own and draws its own modifiers */
+        let mut targeted = self.targeted.clone();
+        targeted.push(attack.target);
+        for _ in extra_targets {
+            let Some(target) = extra_target(world, self.source, &attack, &targeted) else {
+                debug!("{} has no extra target to attack", self.source);
+                break;
+            };
+            follow_ups.push(
+                AttackCommand::new(self.source, Attack { target, ..attack })
+                    .with_targeted(targeted.clone())
+                    .into(),
+            );
+            targeted.push(target);
+        }
+
+        ScenarionCommandExecuteResult::Done(follow_ups)
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        for roll in self.rolls.into_iter().rev() {
+            roll.undo(world);
+        }
+
+        let command = Self {
+            columns: vec![],
+            rolls: vec![],
+            drawn: vec![],
+            modifiers: vec![],
+            final_damage: None,
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.source = entity_mapper.map_entity(self.source);
+        self.attack.target = entity_mapper.map_entity(self.attack.target);
+        for target in &mut self.targeted {
+            *target = entity_mapper.map_entity(*target);
+        }
+        for roll in &mut self.rolls {
+            roll.map_entities(entity_mapper);
+        }
+    }
+
+    fn input(&mut self, choice: usize) {
+        self.columns.extend(ModifierTrayColumn::ALL.get(choice));
+    }
+}
+
+/* Pierce is part of the damage and extra targets need the world, everything else becomes a command */
+fn effect_follow_up(
+    source: Entity,
+    target: Entity,
+    effect: ModifierEffect,
+) -> Option<ScenarioCommand> {
+    match effect {
+        ModifierEffect::Condition(condition) => {
+            Some(AddConditionCommand::new(target, condition).into())
+        }
+        ModifierEffect::Push(distance) => Some(
+            ForcedMovementCommand::new(source, target, ForcedMovementKind::Push, distance).into(),
+        ),
+        ModifierEffect::Pull(distance) => Some(
+            ForcedMovementCommand::new(source, target, ForcedMovementKind::Pull, distance).into(),
+        ),
+        ModifierEffect::Infuse(element) => Some(InfuseElementCommand::new(element).into()),
+        ModifierEffect::HealSelf(heal) => Some(HealCommand::new(source, source, heal).into()),
+        ModifierEffect::Bless => {
+            Some(AddModifierCardCommand::new(source, ModifierCard::bless()).into())
+        }
+        ModifierEffect::Curse => {
+            Some(AddModifierCardCommand::new(target, ModifierCard::curse()).into())
+        }
+        ModifierEffect::Pierce(_) | ModifierEffect::ExtraTarget => None,
+    }
+}
+
+/* The attacked figure deals its retaliate as damage back to the attacker, this is no attack */
+#[derive(Debug, Clone, Reflect)]
+pub struct RetaliateCommand {
+    source: Entity,
+    target: Entity,
+    retaliate: Option<usize>,
+}
+
+impl RetaliateCommand {
+    pub fn new(source: Entity, target: Entity) -> Self {
+        Self {
+            source,
+            target,
+            retaliate: Default::default(),
+        }
+    }
+}
+
+impl ScenarioCommandTrait for RetaliateCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let is_dead = world.get::<Health>(self.source).is_none_or(Health::is_dead);
+        let distance = match (
+            world.get::<HexPosition>(self.source),
+            world.get::<HexPosition>(self.target),
+        ) {
+            (Some(source), Some(target)) => Some(source.hex().unsigned_distance_to(target.hex())),
+            _ => None,
+        };
+
+        let retaliate = match distance {
+            Some(distance) if !is_dead => calculated_retaliate(world, self.source, distance),
+            _ => 0,
+        };
+        self.retaliate = Some(retaliate);
+
+        if retaliate == 0 {
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        }
+
+        debug!(
+            "{} retaliates {} for {}",
+            self.source, self.target, retaliate
+        );
         ScenarionCommandExecuteResult::Done(vec![SufferDamageCommand::new(
             self.source,
-            attack.target,
-            damage,
+            self.target,
+            retaliate,
         )
         .into()])
     }
 
     fn undo(self, _world: &mut World) -> ScenarioCommand {
-        /* TODO: Consider undoing up to AttackCommand? */
+        let command = Self {
+            retaliate: None,
+            ..self
+        };
+        command.into()
+    }
 
-        self.into()
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.source = entity_mapper.map_entity(self.source);
+        self.target = entity_mapper.map_entity(self.target);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hexx::Hex;
+
+    use crate::{
+        figure::{bonus::Retaliate, FigureId, FigureInstance, Initiatives},
+        scenario::{element::Element, map::HexLayer, notation::parse_hex_map},
+    };
+
+    use super::*;
+
+    fn retaliate(attacker: Hex, health_lost: usize) -> (usize, Vec<ScenarioCommand>) {
+        let mut world = World::new();
+        let mut health = Health::new(3);
+        health.suffer(health_lost);
+        let source = world
+            .spawn((
+                HexPosition::new(Hex::ZERO, HexLayer::Figure),
+                health,
+                Retaliate::new(2, 2),
+            ))
+            .id();
+        let target = world
+            .spawn((HexPosition::new(attacker, HexLayer::Figure), Health::new(3)))
+            .id();
+
+        let mut command = RetaliateCommand::new(source, target);
+        let ScenarionCommandExecuteResult::Done(follow_ups) = command.execute(&mut world) else {
+            panic!("Retaliate never waits for input");
+        };
+
+        (command.retaliate.unwrap(), follow_ups)
+    }
+
+    fn advantage(conditions: &[ConditionKind], attack_range: u32, target: Hex) -> AttackAdvantage {
+        let mut world = World::new();
+        let mut source_conditions = Conditions::new(&[]);
+        for condition in conditions {
+            source_conditions.add_condition(*condition);
+        }
+        let source = world
+            .spawn((
+                HexPosition::new(Hex::ZERO, HexLayer::Figure),
+                source_conditions,
+            ))
+            .id();
+        let target = world.spawn(HexPosition::new(target, HexLayer::Figure)).id();
+
+        attack_advantage(
+            &world,
+            source,
+            &Attack::new(target, 2).with_range(attack_range),
+        )
+    }
+
+    #[test]
+    fn advantage_and_disadvantage_cancel_out() {
+        let adjacent = Hex::new(1, 0);
+
+        assert_eq!(
+            advantage(&[ConditionKind::Strengthen], 1, adjacent),
+            AttackAdvantage::Advantage
+        );
+        assert_eq!(
+            advantage(&[ConditionKind::Muddle], 1, adjacent),
+            AttackAdvantage::Disadvantage
+        );
+        assert_eq!(advantage(&[], 3, adjacent), AttackAdvantage::Disadvantage);
+        assert_eq!(advantage(&[], 3, Hex::new(2, 0)), AttackAdvantage::Normal);
+        assert_eq!(
+            advantage(
+                &[ConditionKind::Strengthen, ConditionKind::Muddle],
+                3,
+                adjacent
+            ),
+            AttackAdvantage::Normal
+        );
+    }
+
+    #[test]
+    fn picks_better_or_worse_draw() {
+        let drawn = [Modifier::minus_one(), Modifier::crit()];
+
+        assert_eq!(
+            pick_modifiers(AttackAdvantage::Advantage, &drawn, 2),
+            vec![Modifier::crit()]
+        );
+        assert_eq!(
+            pick_modifiers(AttackAdvantage::Disadvantage, &drawn, 2),
+            vec![Modifier::minus_one()]
+        );
+        /* A tie goes to the first draw */
+        assert_eq!(
+            pick_modifiers(
+                AttackAdvantage::Advantage,
+                &[Modifier::plus_two(), Modifier::crit()],
+                2
+            ),
+            vec![Modifier::plus_two()]
+        );
+    }
+
+    #[test]
+    fn large_damage_saturates_and_never_goes_negative() {
+        let crits = [Modifier::crit(); 40];
+
+        assert_eq!(Modifier::modified_damage(&crits, 200), i32::MAX as usize);
+        assert_eq!(Modifier::modified_damage(&[Modifier::add(-100)], 200), 100);
+        assert_eq!(Modifier::modified_damage(&[Modifier::minus_two()], 1), 0);
+    }
+
+    #[test]
+    fn rolling_modifiers_count_with_their_draw() {
+        let drawn = [
+            Modifier::rolling(1),
+            Modifier::rolling(1),
+            Modifier::zero(),
+            Modifier::plus_one(),
+        ];
+
+        assert_eq!(
+            pick_modifiers(AttackAdvantage::Advantage, &drawn, 2),
+            drawn[..3].to_vec()
+        );
+        assert_eq!(
+            pick_modifiers(AttackAdvantage::Disadvantage, &drawn, 2),
+            vec![Modifier::zero()]
+        );
+    }
+
+    #[test]
+    fn retaliates_within_range() {
+        let (retaliate, follow_ups) = retaliate(Hex::new(2, 0), 0);
+
+        assert_eq!(retaliate, 2);
+        assert!(matches!(
+            follow_ups.as_slice(),
+            [ScenarioCommand::SufferDamageCommand(_)]
+        ));
+    }
+
+    #[test]
+    fn no_retaliate_out_of_range_or_when_dead() {
+        let (out_of_range, follow_ups) = retaliate(Hex::new(3, 0), 0);
+        assert_eq!(out_of_range, 0);
+        assert!(follow_ups.is_empty());
+
+        let (dead, follow_ups) = retaliate(Hex::new(1, 0), 3);
+        assert_eq!(dead, 0);
+        assert!(follow_ups.is_empty());
+    }
+
+    #[test]
+    fn modifier_effects_become_follow_ups() {
+        let mut world = World::new();
+        let source = world.spawn_empty().id();
+        let target = world.spawn_empty().id();
+        let follow_up = |effect| effect_follow_up(source, target, effect);
+
+        assert!(matches!(
+            follow_up(ModifierEffect::Condition(ConditionKind::Wound)),
+            Some(ScenarioCommand::AddConditionCommand(_))
+        ));
+        assert!(matches!(
+            follow_up(ModifierEffect::Push(1)),
+            Some(ScenarioCommand::ForcedMovementCommand(_))
+        ));
+        assert!(matches!(
+            follow_up(ModifierEffect::Infuse(Element::Fire)),
+            Some(ScenarioCommand::InfuseElementCommand(_))
+        ));
+        assert!(matches!(
+            follow_up(ModifierEffect::HealSelf(1)),
+            Some(ScenarioCommand::HealCommand(_))
+        ));
+        assert!(matches!(
+            follow_up(ModifierEffect::Curse),
+            Some(ScenarioCommand::AddModifierCardCommand(_))
+        ));
+        assert!(follow_up(ModifierEffect::Pierce(1)).is_none());
+    }
+
+    #[test]
+    fn extra_target_is_the_closest_enemy_in_range() {
+        let mut world = World::new();
+        let figures = parse_hex_map("P0  M1  A2  M3  M4")
+            .unwrap()
+            .spawn(&mut world.commands());
+        world.flush();
+        let figure = |id| figures[&(FigureId::new(id), FigureInstance::new(0))];
+
+        let attack = Attack::new(figure(1), 2).with_range(3);
+        assert_eq!(
+            extra_target(&world, figure(0), &attack, &[]),
+            Some(figure(3))
+        );
+        assert_eq!(extra_target(&world, figure(0), &attack, &[figure(3)]), None);
+    }
+
+    #[test]
+    fn extra_target_ties_go_to_the_lowest_initiative() {
+        let mut world = World::new();
+        let figures = parse_hex_map(
+            "\
+M1  P0  M2
+  .   M3",
+        )
+        .unwrap()
+        .spawn(&mut world.commands());
+        world.flush();
+        let figure = |id| figures[&(FigureId::new(id), FigureInstance::new(0))];
+
+        let mut initiatives = Initiatives::default();
+        initiatives.set(FigureId::new(1), 50);
+        initiatives.set(FigureId::new(2), 20);
+        world.insert_resource(initiatives);
+
+        let attack = Attack::new(figure(3), 2).with_range(3);
+        assert_eq!(
+            extra_target(&world, figure(0), &attack, &[]),
+            Some(figure(2))
+        );
     }
 }
@@ -0,0 +1,348 @@
+use bevy::{ecs::entity::EntityMapper, prelude::*};
+use serde::Deserialize;
+
+use crate::scenario::command::{
+    ScenarioCommand, ScenarioCommandQueue, ScenarioCommandTrait, ScenarionCommandExecuteResult,
+};
+
+use super::ActiveBonuses;
+
+/* On a figure this is its base shield, on an entity in ActiveBonuses it is added on top */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component, Reflect)]
+#[reflect(Component)]
+pub struct Shield(pub usize);
+
+/* Damage dealt back to attackers within range, on figures and bonuses just like Shield */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect, Deserialize)]
+#[reflect(Component)]
+pub struct Retaliate {
+    pub value: usize,
+    pub range: u32,
+}
+
+impl Retaliate {
+    pub fn new(value: usize, range: u32) -> Self {
+        Self { value, range }
+    }
+}
+
+/* How long a bonus stays in ActiveBonuses */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect)]
+#[reflect(Component)]
+pub enum BonusDuration {
+    Round,
+    Persistent,
+}
+
+/* Bonuses are told apart by this among the ones of the same figure, unlike entities it survives saves and replays */
+/* Bonuses are added and undone in order, so a new one gets the next id after all the others */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect)]
+#[reflect(Component)]
+pub struct BonusId(u32);
+
+/// Base shield of the figure plus all of its active bonuses
+pub fn calculated_shield(world: &World, entity: Entity) -> usize {
+    with_bonuses::<Shield>(world, entity)
+        .map(|shield| shield.0)
+        .sum()
+}
+
+/// Retaliate of the figure and its active bonuses that reaches an attacker at this distance
+/// Each retaliate has its own range, so only the ones in range add up
+pub fn calculated_retaliate(world: &World, entity: Entity, distance: u32) -> usize {
+    with_bonuses::<Retaliate>(world, entity)
+        .filter(|retaliate| retaliate.range >= distance)
+        .map(|retaliate| retaliate.value)
+        .sum()
+}
+
+/* The component on the figure itself followed by the ones on its active bonuses */
+fn with_bonuses<T: Component>(world: &World, entity: Entity) -> impl Iterator<Item = &T> {
+    let bonuses = world
+        .get::<ActiveBonuses>(entity)
+        .into_iter()
+        .flat_map(|active_bonuses| active_bonuses.iter())
+        .filter_map(|bonus| world.get::<T>(bonus));
+
+    world.get::<T>(entity).into_iter().chain(bonuses)
+}
+
+/// The bonus of the figure with this id, active or not
+pub fn find_bonus(world: &World, entity: Entity, id: BonusId) -> Option<Entity> {
+    world
+        .get::<Children>(entity)?
+        .iter()
+        .copied()
+        .find(|bonus| world.get::<BonusId>(*bonus) == Some(&id))
+}
+
+fn next_bonus_id(world: &World, entity: Entity) -> BonusId {
+    let bonuses = world.get::<Children>(entity).into_iter().flatten();
+    let last = bonuses
+        .filter_map(|bonus| world.get::<BonusId>(*bonus))
+        .map(|id| id.0)
+        .max();
+
+    BonusId(last.map_or(0, |last| last + 1))
+}
+
+/// Spawn a bonus as child of the figure, it still has to be added to ActiveBonuses
+pub fn spawn_bonus(
+    world: &mut World,
+    entity: Entity,
+    id: BonusId,
+    duration: BonusDuration,
+    shield: Option<Shield>,
+    retaliate: Option<Retaliate>,
+) -> Entity {
+    let mut bonus = world.spawn((id, duration));
+    if let Some(shield) = shield {
+        bonus.insert(shield);
+    }
+    if let Some(retaliate) = retaliate {
+        bonus.insert(retaliate);
+    }
+    let bonus = bonus.id();
+    world.entity_mut(entity).add_child(bonus);
+
+    bonus
+}
+
+pub fn expire_round_bonuses(
+    mut command_queue: ResMut<ScenarioCommandQueue>,
+    figures: Query<(Entity, &ActiveBonuses)>,
+    durations: Query<&BonusDuration>,
+) {
+    for (entity, active_bonuses) in &figures {
+        let has_round_bonus = active_bonuses
+            .iter()
+            .any(|bonus| durations.get(bonus) == Ok(&BonusDuration::Round));
+
+        if has_round_bonus {
+            command_queue.queue(vec![ExpireBonusesCommand::new(entity).into()]);
+        }
+    }
+}
+
+/* Spawns the bonus as child of the figure, so it goes away together with the scenario */
+#[derive(Debug, Clone, Reflect)]
+pub struct AddBonusCommand {
+    entity: Entity,
+    duration: BonusDuration,
+    shield: Option<Shield>,
+    retaliate: Option<Retaliate>,
+    bonus: Option<BonusId>,
+}
+
+impl AddBonusCommand {
+    pub fn new(entity: Entity, duration: BonusDuration) -> Self {
+        Self {
+            entity,
+            duration,
+            shield: Default::default(),
+            retaliate: Default::default(),
+            bonus: Default::default(),
+        }
+    }
+
+    pub fn with_shield(self, shield: Shield) -> Self {
+        Self {
+            shield: Some(shield),
+            ..self
+        }
+    }
+
+    pub fn with_retaliate(self, retaliate: Retaliate) -> Self {
+        Self {
+            retaliate: Some(retaliate),
+            ..self
+        }
+    }
+}
+
+impl ScenarioCommandTrait for AddBonusCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let id = next_bonus_id(world, self.entity);
+        let bonus = spawn_bonus(
+            world,
+            self.entity,
+            id,
+            self.duration,
+            self.shield,
+            self.retaliate,
+        );
+        world
+            .get_mut::<ActiveBonuses>(self.entity)
+            .unwrap()
+            .insert(bonus);
+        self.bonus = Some(id);
+
+        ScenarionCommandExecuteResult::Done(vec![])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        if let Some(bonus) = self.bonus.and_then(|id| find_bonus(world, self.entity, id)) {
+            world
+                .get_mut::<ActiveBonuses>(self.entity)
+                .unwrap()
+                .remove(bonus);
+            despawn_with_children_recursive(world, bonus, true);
+        }
+
+        let command = Self {
+            bonus: None,
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+/* What an expired bonus was, undo spawns it again under the same id */
+#[derive(Debug, Clone, Reflect)]
+pub struct ExpiredBonus {
+    id: BonusId,
+    duration: BonusDuration,
+    shield: Option<Shield>,
+    retaliate: Option<Retaliate>,
+}
+
+/* Round bonuses are despawned at the end of the round */
+#[derive(Debug, Clone, Reflect)]
+pub struct ExpireBonusesCommand {
+    entity: Entity,
+    expired: Vec<ExpiredBonus>,
+}
+
+impl ExpireBonusesCommand {
+    pub fn new(entity: Entity) -> Self {
+        Self {
+            entity,
+            expired: Default::default(),
+        }
+    }
+}
+
+impl ScenarioCommandTrait for ExpireBonusesCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let Some(active_bonuses) = world.get::<ActiveBonuses>(self.entity) else {
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        };
+
+        let expired: Vec<Entity> = active_bonuses
+            .iter()
+            .filter(|bonus| world.get::<BonusDuration>(*bonus) == Some(&BonusDuration::Round))
+            .collect();
+        self.expired = expired
+            .iter()
+            .filter_map(|bonus| {
+                Some(ExpiredBonus {
+                    id: *world.get::<BonusId>(*bonus)?,
+                    duration: *world.get::<BonusDuration>(*bonus)?,
+                    shield: world.get::<Shield>(*bonus).copied(),
+                    retaliate: world.get::<Retaliate>(*bonus).copied(),
+                })
+            })
+            .collect();
+
+        for bonus in expired {
+            world
+                .get_mut::<ActiveBonuses>(self.entity)
+                .unwrap()
+                .remove(bonus);
+            despawn_with_children_recursive(world, bonus, true);
+        }
+
+        ScenarionCommandExecuteResult::Done(vec![])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        for expired in &self.expired {
+            let bonus = spawn_bonus(
+                world,
+                self.entity,
+                expired.id,
+                expired.duration,
+                expired.shield,
+                expired.retaliate,
+            );
+            world
+                .get_mut::<ActiveBonuses>(self.entity)
+                .unwrap()
+                .insert(bonus);
+        }
+
+        let command = Self {
+            expired: vec![],
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn figure_with_bonuses(world: &mut World, durations: &[BonusDuration]) -> Entity {
+        let entity = world.spawn((Shield(1), ActiveBonuses::default())).id();
+        for duration in durations {
+            AddBonusCommand::new(entity, *duration)
+                .with_shield(Shield(2))
+                .execute(world);
+        }
+
+        entity
+    }
+
+    #[test]
+    fn shield_adds_up_base_and_bonuses() {
+        let mut world = World::new();
+        let entity = figure_with_bonuses(
+            &mut world,
+            &[BonusDuration::Round, BonusDuration::Persistent],
+        );
+
+        assert_eq!(calculated_shield(&world, entity), 5);
+    }
+
+    #[test]
+    fn retaliate_only_adds_up_within_range() {
+        let mut world = World::new();
+        let entity = world
+            .spawn((Retaliate::new(1, 1), ActiveBonuses::default()))
+            .id();
+        AddBonusCommand::new(entity, BonusDuration::Persistent)
+            .with_retaliate(Retaliate::new(2, 3))
+            .execute(&mut world);
+
+        assert_eq!(calculated_retaliate(&world, entity, 1), 3);
+        assert_eq!(calculated_retaliate(&world, entity, 2), 2);
+        assert_eq!(calculated_retaliate(&world, entity, 4), 0);
+    }
+
+    #[test]
+    fn round_bonuses_expire_until_undone() {
+        let mut world = World::new();
+        let entity = figure_with_bonuses(
+            &mut world,
+            &[BonusDuration::Round, BonusDuration::Persistent],
+        );
+
+        let mut command = ExpireBonusesCommand::new(entity);
+        command.execute(&mut world);
+        assert_eq!(calculated_shield(&world, entity), 3);
+        assert_eq!(world.get::<Children>(entity).unwrap().len(), 1);
+
+        command.undo(&mut world);
+        assert_eq!(calculated_shield(&world, entity), 5);
+        assert!(find_bonus(&world, entity, BonusId(0)).is_some());
+    }
+}
@@ -1,17 +1,17 @@
-use bevy::{prelude::*, utils::HashSet};
+use bevy::{ecs::entity::EntityMapper, prelude::*, utils::HashSet};
+use serde::Deserialize;
 
 use crate::{
-    game::{EndOfTurn, StartOfTurn},
+    game::{StartOfTurn, TurnOrder},
     scenario::command::{
         ScenarioCommand, ScenarioCommandQueue, ScenarioCommandTrait, ScenarionCommandExecuteResult,
     },
 };
 
-use super::health::SufferDamageCommand;
+use super::health::{DamageSource, SufferDamageCommand};
 
-/* Each figure has a set of possible conditions and  */
-/* TODO: You need some way to track end of next turn */
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Reflect)]
+/* Each figure has a set of possible conditions and immunities against some of them */
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Reflect, Deserialize)]
 pub enum ConditionKind {
     Invisible,
     Strengthen,
@@ -19,13 +19,37 @@ pub enum ConditionKind {
     Poison,
     Immobilize,
     Disarm,
+    Stun,
     Muddle,
 }
 
+/* When a condition goes away on its own */
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Reflect)]
+pub enum ConditionExpiry {
+    EndOfNextTurn,
+    Heal,
+}
+
+impl ConditionKind {
+    pub fn expiry(&self) -> ConditionExpiry {
+        match self {
+            ConditionKind::Wound | ConditionKind::Poison => ConditionExpiry::Heal,
+            ConditionKind::Invisible
+            | ConditionKind::Strengthen
+            | ConditionKind::Immobilize
+            | ConditionKind::Disarm
+            | ConditionKind::Stun
+            | ConditionKind::Muddle => ConditionExpiry::EndOfNextTurn,
+        }
+    }
+}
+
 /* You want conditions and immunities on the same struct */
-#[derive(Debug, Component, Reflect)]
+#[derive(Debug, Clone, Component, Reflect)]
 pub struct Conditions {
     conditions: HashSet<ConditionKind>,
+    /* Gained during the figure's own turn, so they last past the end of it until the next one */
+    gained_this_turn: HashSet<ConditionKind>,
     /* Immunities are immutable and can only be specified at creation */
     immunities: HashSet<ConditionKind>,
 }
@@ -34,6 +58,7 @@ impl Conditions {
     pub fn new(immunities: &[ConditionKind]) -> Self {
         Self {
             conditions: HashSet::new(),
+            gained_this_turn: HashSet::new(),
             immunities: immunities.iter().copied().collect(),
         }
     }
@@ -44,8 +69,17 @@ impl Conditions {
         }
     }
 
+    /// Add a condition during the figure's own turn, it expires at the end of its next turn
+    pub fn add_condition_this_turn(&mut self, condition: ConditionKind) {
+        if !self.immunities.contains(&condition) {
+            self.conditions.insert(condition);
+            self.gained_this_turn.insert(condition);
+        }
+    }
+
     pub fn remove_condition(&mut self, condition: ConditionKind) {
         self.conditions.remove(&condition);
+        self.gained_this_turn.remove(&condition);
     }
 
     pub fn has(&self, condition: ConditionKind) -> bool {
@@ -55,30 +89,47 @@ impl Conditions {
     pub fn is_immune(&self, condition: ConditionKind) -> bool {
         self.immunities.contains(&condition)
     }
+
+    pub fn immunities(&self) -> impl Iterator<Item = ConditionKind> + '_ {
+        self.immunities.iter().copied()
+    }
+
+    pub fn gained_this_turn(&self, condition: ConditionKind) -> bool {
+        self.gained_this_turn.contains(&condition)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ConditionKind> + '_ {
+        self.conditions.iter().copied()
+    }
 }
 
-pub fn _take_wound_damage(
+pub fn take_wound_damage(
     mut command_queue: ResMut<ScenarioCommandQueue>,
     mut start_of_turn: EventReader<StartOfTurn>,
+    conditions: Query<&Conditions>,
 ) {
     for event in start_of_turn.read() {
-        /* TODO: I dont have a proper source here */
-        let commands = vec![SufferDamageCommand::new(event.entity, event.entity, 1).into()];
-        command_queue.queue(commands);
+        let Ok(conditions) = conditions.get(event.entity) else {
+            continue;
+        };
+
+        if conditions.has(ConditionKind::Wound) {
+            command_queue.queue(vec![SufferDamageCommand::new(
+                DamageSource::Condition(ConditionKind::Wound),
+                event.entity,
+                1,
+            )
+            .into()]);
+        }
     }
 }
 
-pub fn _remove_condition_on_heal() {}
-
-pub fn _remove_condition_on_end_of_turn(
-    mut command_queue: ResMut<ScenarioCommandQueue>,
-    mut end_of_turn: EventReader<EndOfTurn>,
-) {
-    for event in end_of_turn.read() {
-        /* TODO: I dont have a proper source here */
-        let commands = vec![SufferDamageCommand::new(event.entity, event.entity, 1).into()];
-        command_queue.queue(commands);
-    }
+/* Whether it is the entity's own turn right now, conditions gained then last longer */
+fn is_own_turn(world: &World, entity: Entity) -> bool {
+    world
+        .get_resource::<TurnOrder>()
+        .and_then(TurnOrder::current)
+        == Some(entity)
 }
 
 #[derive(Debug, Clone, Reflect)]
@@ -86,6 +137,8 @@ pub struct AddConditionCommand {
     entity: Entity,
     condition: ConditionKind,
     added: bool,
+    /* Adding a condition again during the own turn marks it, undo has to take that back */
+    gained_this_turn: bool,
 }
 
 impl AddConditionCommand {
@@ -94,17 +147,24 @@ impl AddConditionCommand {
             entity,
             condition,
             added: Default::default(),
+            gained_this_turn: Default::default(),
         }
     }
 }
 
 impl ScenarioCommandTrait for AddConditionCommand {
     fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let own_turn = is_own_turn(world, self.entity);
         let mut entity = world.entity_mut(self.entity);
         let mut conditions = entity.get_mut::<Conditions>().unwrap();
 
         self.added = !conditions.has(self.condition) && !conditions.is_immune(self.condition);
-        conditions.add_condition(self.condition);
+        self.gained_this_turn = conditions.gained_this_turn(self.condition);
+        if own_turn {
+            conditions.add_condition_this_turn(self.condition);
+        } else {
+            conditions.add_condition(self.condition);
+        }
 
         ScenarionCommandExecuteResult::Done(vec![])
     }
@@ -116,14 +176,24 @@ impl ScenarioCommandTrait for AddConditionCommand {
             let mut conditions = entity.get_mut::<Conditions>().unwrap();
 
             conditions.remove_condition(self.condition);
+        } else if !self.gained_this_turn {
+            let mut entity: EntityWorldMut<'_> = world.entity_mut(self.entity);
+            let mut conditions = entity.get_mut::<Conditions>().unwrap();
+
+            conditions.gained_this_turn.remove(&self.condition);
         }
 
         let command = Self {
             added: false,
+            gained_this_turn: false,
             ..self
         };
         command.into()
     }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
 }
 
 #[derive(Debug, Clone, Reflect)]
@@ -131,6 +201,7 @@ pub struct RemoveConditionCommand {
     entity: Entity,
     condition: ConditionKind,
     removed: bool,
+    gained_this_turn: bool,
 }
 
 impl RemoveConditionCommand {
@@ -139,6 +210,7 @@ impl RemoveConditionCommand {
             entity,
             condition,
             removed: Default::default(),
+            gained_this_turn: Default::default(),
         }
     }
 }
@@ -149,6 +221,7 @@ impl ScenarioCommandTrait for RemoveConditionCommand {
         let mut conditions = entity.get_mut::<Conditions>().unwrap();
 
         self.removed = conditions.has(self.condition);
+        self.gained_this_turn = conditions.gained_this_turn(self.condition);
         conditions.remove_condition(self.condition);
 
         ScenarionCommandExecuteResult::Done(vec![])
@@ -160,13 +233,179 @@ impl ScenarioCommandTrait for RemoveConditionCommand {
             let mut entity: EntityWorldMut<'_> = world.entity_mut(self.entity);
             let mut conditions = entity.get_mut::<Conditions>().unwrap();
 
-            conditions.add_condition(self.condition);
+            if self.gained_this_turn {
+                conditions.add_condition_this_turn(self.condition);
+            } else {
+                conditions.add_condition(self.condition);
+            }
         }
 
         let command = Self {
             removed: false,
+            gained_this_turn: false,
             ..self
         };
         command.into()
     }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+/* Follows the EndTurnCommand of the entity, so it is undone together with the turn */
+/* Conditions gained during that same turn only lose that mark and expire at the end of the next one */
+#[derive(Debug, Clone, Reflect)]
+pub struct ExpireConditionsCommand {
+    entity: Entity,
+    expired: Vec<ConditionKind>,
+    kept: Vec<ConditionKind>,
+}
+
+impl ExpireConditionsCommand {
+    pub fn new(entity: Entity) -> Self {
+        Self {
+            entity,
+            expired: Default::default(),
+            kept: Default::default(),
+        }
+    }
+}
+
+impl ScenarioCommandTrait for ExpireConditionsCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        /* The figure might have died during its turn */
+        let Some(mut conditions) = world.get_mut::<Conditions>(self.entity) else {
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        };
+
+        let mut expiring: Vec<ConditionKind> = conditions
+            .iter()
+            .filter(|condition| condition.expiry() == ConditionExpiry::EndOfNextTurn)
+            .collect();
+        /* Keep the order stable so undo and replays see the same thing */
+        expiring.sort_by_key(|condition| *condition as usize);
+
+        for condition in expiring {
+            if conditions.gained_this_turn(condition) {
+                conditions.gained_this_turn.remove(&condition);
+                self.kept.push(condition);
+            } else {
+                conditions.remove_condition(condition);
+                self.expired.push(condition);
+            }
+        }
+        if !self.expired.is_empty() {
+            debug!("{:?} of {} expired", self.expired, self.entity);
+        }
+
+        ScenarionCommandExecuteResult::Done(vec![])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        if let Some(mut conditions) = world.get_mut::<Conditions>(self.entity) {
+            for condition in &self.expired {
+                conditions.add_condition(*condition);
+            }
+            for condition in &self.kept {
+                conditions.gained_this_turn.insert(*condition);
+            }
+        }
+
+        let command = Self {
+            expired: vec![],
+            kept: vec![],
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        figure::{FigureId, FigureInstance, Initiatives, Team},
+        game::TurnOrderFigure,
+    };
+
+    use super::*;
+
+    fn expire(world: &mut World, entity: Entity) -> ExpireConditionsCommand {
+        let mut command = ExpireConditionsCommand::new(entity);
+        command.execute(world);
+        command
+    }
+
+    #[test]
+    fn conditions_expire_at_the_end_of_the_next_turn() {
+        let mut world = World::new();
+        let mut conditions = Conditions::new(&[]);
+        conditions.add_condition(ConditionKind::Muddle);
+        conditions.add_condition(ConditionKind::Wound);
+        conditions.add_condition_this_turn(ConditionKind::Strengthen);
+        let entity = world.spawn(conditions).id();
+
+        expire(&mut world, entity);
+        let conditions = world.get::<Conditions>(entity).unwrap();
+        assert!(!conditions.has(ConditionKind::Muddle));
+        assert!(conditions.has(ConditionKind::Wound));
+        assert!(conditions.has(ConditionKind::Strengthen));
+
+        expire(&mut world, entity);
+        let conditions = world.get::<Conditions>(entity).unwrap();
+        assert!(conditions.has(ConditionKind::Wound));
+        assert!(!conditions.has(ConditionKind::Strengthen));
+    }
+
+    #[test]
+    fn undo_restores_expired_conditions() {
+        let mut world = World::new();
+        let mut conditions = Conditions::new(&[]);
+        conditions.add_condition(ConditionKind::Immobilize);
+        conditions.add_condition_this_turn(ConditionKind::Invisible);
+        let entity = world.spawn(conditions).id();
+
+        expire(&mut world, entity).undo(&mut world);
+
+        let conditions = world.get::<Conditions>(entity).unwrap();
+        assert!(conditions.has(ConditionKind::Immobilize));
+        assert!(conditions.gained_this_turn(ConditionKind::Invisible));
+    }
+
+    #[test]
+    fn undo_keeps_conditions_added_again_on_the_own_turn() {
+        let mut world = World::new();
+        let mut conditions = Conditions::new(&[]);
+        conditions.add_condition(ConditionKind::Strengthen);
+        let entity = world.spawn(conditions).id();
+        let mut initiatives = Initiatives::default();
+        initiatives.set(FigureId::new(0), 10);
+        let figure = TurnOrderFigure {
+            entity,
+            id: FigureId::new(0),
+            instance: FigureInstance::new(0),
+            team: Team::Player,
+            rank: None,
+            summon: false,
+        };
+        let mut turn_order = TurnOrder::new(vec![figure], &initiatives);
+        turn_order.advance();
+        world.insert_resource(turn_order);
+
+        let mut command = AddConditionCommand::new(entity, ConditionKind::Strengthen);
+        command.execute(&mut world);
+        assert!(world
+            .get::<Conditions>(entity)
+            .unwrap()
+            .gained_this_turn(ConditionKind::Strengthen));
+
+        command.undo(&mut world);
+        let conditions = world.get::<Conditions>(entity).unwrap();
+        assert!(conditions.has(ConditionKind::Strengthen));
+        assert!(!conditions.gained_this_turn(ConditionKind::Strengthen));
+    }
 }
@@ -1,13 +1,21 @@
-use bevy::prelude::*;
+use bevy::{ecs::entity::EntityMapper, prelude::*};
+use hexx::Hex;
 
-use crate::scenario::command::{
-    ScenarioCommand, ScenarioCommandTrait, ScenarionCommandExecuteResult,
+use crate::{
+    game::{RemovedTurn, TurnOrder},
+    scenario::{
+        command::{ScenarioCommand, ScenarioCommandTrait, ScenarionCommandExecuteResult},
+        input::InputRequest,
+        map::{HexGrid, HexLayer, HexPosition, OverlayKind},
+    },
 };
 
-#[derive(Debug, Default, Component, Reflect)]
-pub struct CalculatedHealth(usize);
+use super::{
+    condition::{ConditionKind, Conditions, RemoveConditionCommand},
+    Team,
+};
 
-#[derive(Debug, Component, Reflect)]
+#[derive(Debug, Clone, Component, Reflect)]
 pub struct Health {
     current: usize,
     max: usize,
@@ -18,6 +26,18 @@ impl Health {
         Self { max, current: max }
     }
 
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current == 0
+    }
+
     pub fn suffer(&mut self, damage: usize) -> usize {
         let actual_damage = self.current.min(damage);
 
@@ -27,34 +47,72 @@ impl Health {
     }
 
     pub fn heal(&mut self, heal: usize) -> usize {
-        let new_current = self.current.saturating_add(heal);
-        let actual_heal = new_current - self.current;
+        let actual_heal = heal.min(self.max.saturating_sub(self.current));
 
-        self.current = new_current;
+        self.current += actual_heal;
         actual_heal
     }
+
+    /// Give back damage that is undone, this is not a heal and nothing reacts to it
+    pub fn undo_damage(&mut self, actual_damage: usize) {
+        self.current += actual_damage;
+    }
+
+    /// Take back health that an undone heal gave
+    pub fn undo_heal(&mut self, actual_heal: usize) {
+        self.current -= actual_heal;
+    }
 }
 
-/* This is fired whenever an entity is healed */
+/* This is fired whenever an entity is healed, amount is 0 if the heal only removed Poison */
 #[derive(Debug, Event, Reflect)]
 pub struct Healed {
     pub entity: Entity,
+    pub amount: usize,
+}
+
+/* Whatever caused the damage, only figures can be retaliated against */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum DamageSource {
+    Figure(Entity),
+    Condition(ConditionKind),
+    Overlay(OverlayKind),
+}
+
+impl From<Entity> for DamageSource {
+    fn from(entity: Entity) -> Self {
+        Self::Figure(entity)
+    }
+}
+
+/* Characters can choose to lose a card instead of suffering damage */
+/* The players hold their cards themselves, like a real die for the tray, so they take it out of their hand */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum DamageChoice {
+    Suffer,
+    LoseCard,
+}
+
+impl DamageChoice {
+    pub const ALL: [DamageChoice; 2] = [DamageChoice::Suffer, DamageChoice::LoseCard];
 }
 
 #[derive(Debug, Clone, Reflect)]
 pub struct SufferDamageCommand {
-    source: Entity,
+    source: DamageSource,
     target: Entity,
     damage: usize,
+    choice: Option<DamageChoice>,
     actual_damage: Option<usize>,
 }
 
 impl SufferDamageCommand {
-    pub fn new(source: Entity, target: Entity, damage: usize) -> Self {
+    pub fn new(source: impl Into<DamageSource>, target: Entity, damage: usize) -> Self {
         Self {
-            source,
+            source: source.into(),
             target,
             damage,
+            choice: Default::default(),
             actual_damage: Default::default(),
         }
     }
@@ -63,10 +121,32 @@ impl SufferDamageCommand {
 impl ScenarioCommandTrait for SufferDamageCommand {
     fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
         let mut target = world.entity_mut(self.target);
+
+        let is_player = matches!(target.get::<Team>(), Some(Team::Player));
+        if is_player && self.damage > 0 && self.choice.is_none() {
+            return ScenarionCommandExecuteResult::Pending(InputRequest::new(
+                format!("Suffer {} damage?", self.damage),
+                vec!["Suffer damage".to_string(), "Lose a card".to_string()],
+            ));
+        }
+
         let mut health = target.get_mut::<Health>().unwrap();
-        self.actual_damage = Some(health.suffer(self.damage));
+        let actual_damage = match self.choice.unwrap_or(DamageChoice::Suffer) {
+            DamageChoice::Suffer => health.suffer(self.damage),
+            DamageChoice::LoseCard => {
+                debug!(
+                    "{} loses a card instead of {} damage",
+                    self.target, self.damage
+                );
+                0
+            }
+        };
+        self.actual_damage = Some(actual_damage);
 
-        /* TODO: Should be Pending until user input event is received */
+        /* Only the damage that brought it down kills it, not any after */
+        if actual_damage > 0 && health.is_dead() {
+            return ScenarionCommandExecuteResult::Done(vec![KillCommand::new(self.target).into()]);
+        }
         ScenarionCommandExecuteResult::Done(vec![])
     }
 
@@ -74,13 +154,244 @@ impl ScenarioCommandTrait for SufferDamageCommand {
         let mut target = world.entity_mut(self.target);
         let mut health = target.get_mut::<Health>().unwrap();
 
-        /* TODO: Might not want to make this heal for semantic reasons */
-        health.heal(self.actual_damage.unwrap());
+        health.undo_damage(self.actual_damage.unwrap());
 
         let command = Self {
+            choice: None,
             actual_damage: None,
             ..self
         };
         command.into()
     }
+
+    /* Anything but one of the options is asked for again */
+    fn input(&mut self, choice: usize) {
+        self.choice = DamageChoice::ALL.get(choice).copied();
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        if let DamageSource::Figure(source) = &mut self.source {
+            *source = entity_mapper.map_entity(*source);
+        }
+        self.target = entity_mapper.map_entity(self.target);
+    }
+}
+
+/* A figure at 0 health leaves the map and the turn order, its entity stays around for undo */
+#[derive(Debug, Clone, Reflect)]
+pub struct KillCommand {
+    entity: Entity,
+    /* The hex it was taken off */
+    hex: Option<Hex>,
+    removed_turn: Option<RemovedTurn>,
+}
+
+impl KillCommand {
+    pub fn new(entity: Entity) -> Self {
+        Self {
+            entity,
+            hex: Default::default(),
+            removed_turn: Default::default(),
+        }
+    }
+
+    fn hex_grid(world: &mut World, entity: Entity) -> Option<Mut<'_, HexGrid>> {
+        let hex_grid = world.get::<Parent>(entity)?.get();
+        world.get_mut::<HexGrid>(hex_grid)
+    }
+}
+
+impl ScenarioCommandTrait for KillCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let hex = world.get::<HexPosition>(self.entity).unwrap().hex();
+        if let Some(mut hex_grid) = Self::hex_grid(world, self.entity) {
+            if hex_grid.get(&hex, &HexLayer::Figure) == Some(self.entity) {
+                hex_grid.remove(&hex, &HexLayer::Figure);
+                self.hex = Some(hex);
+            }
+        }
+        self.removed_turn = world
+            .get_resource_mut::<TurnOrder>()
+            .and_then(|mut turn_order| turn_order.remove(self.entity));
+
+        debug!("{} dies", self.entity);
+
+        ScenarionCommandExecuteResult::Done(vec![])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        if let Some(hex) = self.hex {
+            let mut hex_grid = Self::hex_grid(world, self.entity).unwrap();
+            hex_grid.insert(hex, &HexLayer::Figure, self.entity);
+        }
+        if let Some(removed_turn) = self.removed_turn {
+            world
+                .resource_mut::<TurnOrder>()
+                .restore(self.entity, removed_turn);
+        }
+
+        let command = Self {
+            hex: None,
+            removed_turn: None,
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+/* Heal removes Wound and Poison, but a poisoned figure does not regain any health */
+#[derive(Debug, Clone, Reflect)]
+pub struct HealCommand {
+    source: Entity,
+    target: Entity,
+    heal: usize,
+    actual_heal: Option<usize>,
+}
+
+impl HealCommand {
+    pub fn new(source: Entity, target: Entity, heal: usize) -> Self {
+        Self {
+            source,
+            target,
+            heal,
+            actual_heal: Default::default(),
+        }
+    }
+}
+
+impl ScenarioCommandTrait for HealCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let mut target = world.entity_mut(self.target);
+        let conditions = target.get::<Conditions>().unwrap();
+        let removed: Vec<ConditionKind> = [ConditionKind::Poison, ConditionKind::Wound]
+            .into_iter()
+            .filter(|condition| conditions.has(*condition))
+            .collect();
+
+        let actual_heal = if removed.contains(&ConditionKind::Poison) {
+            0
+        } else {
+            target.get_mut::<Health>().unwrap().heal(self.heal)
+        };
+        self.actual_heal = Some(actual_heal);
+
+        debug!("Heal {} for {}", self.target, actual_heal);
+        world.send_event(Healed {
+            entity: self.target,
+            amount: actual_heal,
+        });
+
+        ScenarionCommandExecuteResult::Done(
+            removed
+                .into_iter()
+                .map(|condition| RemoveConditionCommand::new(self.target, condition).into())
+                .collect(),
+        )
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        let mut target = world.entity_mut(self.target);
+        let mut health = target.get_mut::<Health>().unwrap();
+
+        health.undo_heal(self.actual_heal.unwrap());
+
+        let command = Self {
+            actual_heal: None,
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.source = entity_mapper.map_entity(self.source);
+        self.target = entity_mapper.map_entity(self.target);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heal(conditions: &[ConditionKind]) -> (usize, Vec<ScenarioCommand>) {
+        let mut world = World::new();
+        let mut figure_conditions = Conditions::new(&[]);
+        for condition in conditions {
+            figure_conditions.add_condition(*condition);
+        }
+        let mut health = Health::new(10);
+        health.suffer(5);
+        let entity = world.spawn((health, figure_conditions)).id();
+
+        let mut command = HealCommand::new(entity, entity, 3);
+        let ScenarionCommandExecuteResult::Done(follow_ups) = command.execute(&mut world) else {
+            panic!("Heal never waits for input");
+        };
+
+        (command.actual_heal.unwrap(), follow_ups)
+    }
+
+    #[test]
+    fn heal_removes_wound() {
+        let (actual_heal, follow_ups) = heal(&[ConditionKind::Wound]);
+
+        assert_eq!(actual_heal, 3);
+        assert!(matches!(
+            follow_ups.as_slice(),
+            [ScenarioCommand::RemoveConditionCommand(_)]
+        ));
+    }
+
+    #[test]
+    fn heal_stops_at_max_health() {
+        let mut health = Health::new(10);
+        health.suffer(2);
+
+        assert_eq!(health.heal(5), 2);
+        assert_eq!(health.heal(5), 0);
+    }
+
+    #[test]
+    fn undo_restores_health_before_heal() {
+        let mut world = World::new();
+        let mut health = Health::new(10);
+        health.suffer(1);
+        let entity = world.spawn((health, Conditions::new(&[]))).id();
+
+        let mut command = HealCommand::new(entity, entity, 3);
+        command.execute(&mut world);
+        command.undo(&mut world);
+
+        assert_eq!(world.get::<Health>(entity).unwrap().current, 9);
+    }
+
+    #[test]
+    fn poison_cancels_heal() {
+        let (actual_heal, follow_ups) = heal(&[ConditionKind::Poison, ConditionKind::Wound]);
+
+        assert_eq!(actual_heal, 0);
+        assert_eq!(follow_ups.len(), 2);
+    }
+
+    #[test]
+    fn characters_lose_a_card_instead_of_suffering_damage() {
+        let mut world = World::new();
+        let entity = world.spawn((Health::new(10), Team::Player)).id();
+
+        let mut command = SufferDamageCommand::new(entity, entity, 3);
+        command.execute(&mut world);
+        command.input(2);
+        assert!(matches!(
+            command.execute(&mut world),
+            ScenarionCommandExecuteResult::Pending(_)
+        ));
+
+        command.input(1);
+        command.execute(&mut world);
+        assert_eq!(command.actual_damage, Some(0));
+        assert_eq!(world.get::<Health>(entity).unwrap().current, 10);
+    }
 }
@@ -1,5 +1,6 @@
 pub mod ai;
 pub mod attack;
+pub mod bonus;
 pub mod condition;
 pub mod health;
 pub mod modifier;
@@ -12,12 +13,22 @@ pub mod movement;
 /* CalculatedRetaliate: Base [+] any bonuses as Retaliate(usize, usize) */
 /* AttackEffects: Vec of AttackEffects that are added to any attack this figure does */
 
+use ai::{take_monster_turns, MonsterAbilities};
 use bevy::{prelude::*, utils::HashMap};
-use condition::{ConditionKind, Conditions};
-use health::Health;
-use modifier::{Modifier, ModifierTray, ModifierTrayColumn, ModifierTrays};
+use bonus::{expire_round_bonuses, BonusDuration, BonusId, ExpiredBonus, Retaliate, Shield};
+use condition::{take_wound_damage, ConditionExpiry, ConditionKind, Conditions};
+use health::{DamageSource, Healed, Health};
+use modifier::{
+    deck::{ModifierCard, ModifierCardKind, ModifierDeck},
+    reset_modifier_sources, Modifier, ModifierEffect, ModifierSourceOwner, ModifierSourceState,
+    ModifierSources, ModifierTray, ModifierTrayColumn, ModifierTrayPosition, ModifierValue,
+};
+use serde::Deserialize;
 
-use crate::scenario::map::HexPosition;
+use crate::{
+    game::{not_restoring_save, RoundState},
+    scenario::map::HexPosition,
+};
 
 pub struct FigurePlugin;
 
@@ -26,55 +37,178 @@ impl Plugin for FigurePlugin {
         app.register_type::<Team>()
             .register_type::<Health>()
             .register_type::<Conditions>()
-            .register_type::<ConditionKind>();
+            .register_type::<ConditionKind>()
+            .register_type::<ConditionExpiry>();
+        app.add_systems(
+            Update,
+            (
+                take_wound_damage,
+                take_monster_turns.after(take_wound_damage),
+            ),
+        );
+        app.register_type::<DamageSource>()
+            .register_type::<Healed>();
+        app.add_event::<Healed>();
 
-        app.register_type::<FigureId>();
+        app.register_type::<Shield>()
+            .register_type::<Retaliate>()
+            .register_type::<BonusDuration>()
+            .register_type::<BonusId>()
+            .register_type::<ExpiredBonus>()
+            .register_type::<ActiveBonuses>();
+        app.add_systems(
+            OnEnter(RoundState::EndOfRound),
+            expire_round_bonuses.run_if(not_restoring_save),
+        );
+
+        app.register_type::<FigureId>()
+            .register_type::<FigureInstance>()
+            .register_type::<MonsterRank>()
+            .register_type::<Summon>()
+            .register_type::<Initiatives>()
+            .register_type::<MonsterAbilities>();
+        app.init_resource::<Initiatives>()
+            .init_resource::<MonsterAbilities>();
 
         app.register_type::<Modifier>()
+            .register_type::<ModifierValue>()
+            .register_type::<ModifierEffect>()
             .register_type::<ModifierTrayColumn>()
             .register_type::<ModifierTray>()
-            .register_type::<ModifierTrays>();
-        app.init_resource::<ModifierTrays>();
+            .register_type::<ModifierTrayPosition>()
+            .register_type::<ModifierCardKind>()
+            .register_type::<ModifierCard>()
+            .register_type::<ModifierDeck>()
+            .register_type::<ModifierSourceOwner>()
+            .register_type::<ModifierSourceState>()
+            .register_type::<ModifierSources>();
+        app.init_resource::<ModifierSources>();
+        app.add_systems(
+            OnEnter(RoundState::EndOfRound),
+            reset_modifier_sources.run_if(not_restoring_save),
+        );
     }
 }
 
 #[derive(Debug, Bundle)]
 pub struct FigureBundle {
-    pub mesh_2d: Mesh2d,
-    pub mesh_material_2d: MeshMaterial2d<ColorMaterial>,
     pub hex_position: HexPosition,
     pub health: Health,
     pub conditions: Conditions,
+    pub shield: Shield,
+    pub active_bonuses: ActiveBonuses,
+    pub team: Team,
     pub id: FigureId,
+    pub instance: FigureInstance,
 }
 
 /* TODO: Or should those be marker component to query for? */
-#[derive(Debug, Component, Reflect)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect)]
 pub enum Team {
     Monster,
     Player,
     Ally,
 }
 
+impl Team {
+    /* Players and their allies fight together against the monsters */
+    pub fn is_ally_of(&self, other: &Team) -> bool {
+        matches!(
+            (self, other),
+            (Team::Monster, Team::Monster) | (Team::Player | Team::Ally, Team::Player | Team::Ally)
+        )
+    }
+}
+
+/* Monsters come as normal or elite variant of the same FigureId */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect, Deserialize)]
+pub enum MonsterRank {
+    Normal,
+    Elite,
+}
+
 /* This is a list of entities that have bonuses like Health, Shield, Retaliate, AttackEffects */
-#[derive(Debug, Component, Reflect)]
+#[derive(Debug, Default, Clone, Component, Reflect)]
 pub struct ActiveBonuses {
     bonuses: Vec<Entity>,
 }
 
+impl ActiveBonuses {
+    pub fn insert(&mut self, bonus: Entity) {
+        self.bonuses.push(bonus);
+    }
+
+    pub fn remove(&mut self, bonus: Entity) {
+        self.bonuses.retain(|active_bonus| *active_bonus != bonus);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.bonuses.iter().copied()
+    }
+}
+
 /* This is an identifier for each type of figure. */
 /* e.g. Craigheart might be 0 and Skeleton might be 1 */
-/* TODO: Summons should have the same id as the owner */
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component, Reflect)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component, Reflect, Deserialize)]
+#[reflect(Hash, PartialEq, Deserialize)]
 pub struct FigureId(u32);
 
 impl FigureId {
     pub fn new(id: u32) -> Self {
         Self(id)
     }
+
+    pub fn id(&self) -> u32 {
+        self.0
+    }
 }
 
-#[derive(Debug, Default, Resource, Reflect)]
+/* This tells apart figures with the same FigureId, e.g. the second Skeleton is instance 1 */
+/* Together with FigureId it identifies a figure across save files and replays */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component, Reflect)]
+pub struct FigureInstance(u32);
+
+impl FigureInstance {
+    pub fn new(instance: u32) -> Self {
+        Self(instance)
+    }
+}
+
+/* Summons act right before the figure that summoned them and share its FigureId */
+#[derive(Debug, Component, Reflect)]
+pub struct Summon;
+
+/* Characters and monster types act on the initiative of the card they played this round */
+#[derive(Debug, Default, Clone, Resource, Reflect)]
+#[reflect(Resource)]
 pub struct Initiatives {
     initiatives: HashMap<FigureId, u8>,
+    /* Whoever comes first acts first if the initiatives are tied, as the players decided */
+    tiebreaks: Vec<FigureId>,
+}
+
+impl Initiatives {
+    pub fn get(&self, id: &FigureId) -> Option<u8> {
+        self.initiatives.get(id).copied()
+    }
+
+    pub fn set(&mut self, id: FigureId, initiative: u8) {
+        self.initiatives.insert(id, initiative);
+    }
+
+    pub fn tiebreaks(&self) -> &[FigureId] {
+        &self.tiebreaks
+    }
+
+    pub fn set_tiebreaks(&mut self, tiebreaks: Vec<FigureId>) {
+        self.tiebreaks = tiebreaks;
+    }
+
+    /// Position among tied initiatives, anyone the players did not order comes last
+    pub fn tiebreak(&self, id: &FigureId) -> usize {
+        self.tiebreaks
+            .iter()
+            .position(|tiebreak| tiebreak == id)
+            .unwrap_or(self.tiebreaks.len())
+    }
 }
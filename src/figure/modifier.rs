@@ -1,72 +1,229 @@
 use bevy::{
-    ecs::{component::ComponentId, world::DeferredWorld},
+    ecs::{component::ComponentId, entity::EntityMapper, world::DeferredWorld},
     prelude::*,
     utils::HashMap,
 };
-
-use crate::scenario::command::{
-    ScenarioCommand, ScenarioCommandTrait, ScenarionCommandExecuteResult,
+use serde::Deserialize;
+
+use crate::scenario::{
+    command::{
+        ScenarioCommand, ScenarioCommandQueue, ScenarioCommandTrait, ScenarionCommandExecuteResult,
+    },
+    element::Element,
+    input::InputRequest,
+    rng::ScenarioRng,
 };
 
-use super::FigureId;
+use super::{attack::AttackAdvantage, condition::ConditionKind, FigureId, Team};
+
+pub mod deck;
+
+use deck::ModifierDeck;
 
 /*
-    This defines ModifierTray (component) entities for each figure id
-    and all modifiers
+    This defines the modifier sources of each figure id and all modifiers
+    The ruleset of the scenario decides whether figures roll from a ModifierTray
+    or draw from a ModifierDeck, both are components found through ModifierSources
+    In Gloomhaven all monsters without a deck of their own share a single one
+    A figure without any modifier source draws a neutral modifier
+
+    Buttons & Bugs rolls modifiers from a tray instead of drawing cards:
+    - a die picks the Minus, Neutral or Plus column of the active row
+    - players rolling a real die are asked for the column it showed instead
+    - advantage shifts the rolled column one towards Plus, disadvantage one towards Minus
+    - every roll advances to the next row, after the last row the rows are shuffled
+    - rolling a Multiply resets the tray at the end of the round, shuffled and back on the first row
 */
 
-#[derive(Debug, Clone, Copy, Reflect)]
-pub enum Modifier {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, Deserialize)]
+pub enum ModifierValue {
     Add(i8),
     Multiply(i8),
 }
 
+/* Anything a modifier does besides changing the damage, ApplyAttackCommand turns these into follow-ups */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, Deserialize)]
+pub enum ModifierEffect {
+    Condition(ConditionKind),
+    Push(u32),
+    Pull(u32),
+    Infuse(Element),
+    HealSelf(usize),
+    Pierce(usize),
+    ExtraTarget,
+    /* A Bless card goes into the attacker's deck, a Curse card into the target's */
+    Bless,
+    Curse,
+}
+
+/* Scenario files write plain values as Add(1) and anything else as Modifier(value: Add(1), rolling: true) */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, Deserialize)]
+#[serde(from = "ModifierDefinition")]
+pub struct Modifier {
+    pub value: ModifierValue,
+    /* Another modifier is drawn on top */
+    pub rolling: bool,
+    pub effect: Option<ModifierEffect>,
+}
+
+#[derive(Deserialize)]
+enum ModifierDefinition {
+    Add(i8),
+    Multiply(i8),
+    Modifier {
+        value: ModifierValue,
+        #[serde(default)]
+        rolling: bool,
+        #[serde(default)]
+        effect: Option<ModifierEffect>,
+    },
+}
+
+impl From<ModifierDefinition> for Modifier {
+    fn from(definition: ModifierDefinition) -> Self {
+        match definition {
+            ModifierDefinition::Add(value) => Self::add(value),
+            ModifierDefinition::Multiply(value) => Self::multiply(value),
+            ModifierDefinition::Modifier {
+                value,
+                rolling,
+                effect,
+            } => Self {
+                value,
+                rolling,
+                effect,
+            },
+        }
+    }
+}
+
 impl Modifier {
+    pub fn new(value: ModifierValue) -> Self {
+        Self {
+            value,
+            rolling: false,
+            effect: None,
+        }
+    }
+
     pub fn add(value: i8) -> Self {
-        Self::Add(value)
+        Self::new(ModifierValue::Add(value))
     }
 
     pub fn multiply(value: i8) -> Self {
-        Self::Multiply(value)
+        Self::new(ModifierValue::Multiply(value))
     }
 
     pub fn zero() -> Self {
-        Self::Add(0)
+        Self::add(0)
     }
 
     pub fn plus_one() -> Self {
-        Self::Add(1)
+        Self::add(1)
     }
 
     pub fn minus_one() -> Self {
-        Self::Add(-1)
+        Self::add(-1)
     }
 
     pub fn plus_two() -> Self {
-        Self::Add(2)
+        Self::add(2)
     }
 
     pub fn minus_two() -> Self {
-        Self::Add(-2)
+        Self::add(-2)
     }
 
     pub fn miss() -> Self {
-        Self::Multiply(0)
+        Self::multiply(0)
     }
 
     pub fn crit() -> Self {
-        Self::Multiply(2)
+        Self::multiply(2)
+    }
+
+    /* Decks and trays come with rolling modifiers and effects from the scenario definition */
+    #[cfg(test)]
+    pub fn rolling(value: i8) -> Self {
+        Self {
+            rolling: true,
+            ..Self::add(value)
+        }
     }
 
-    pub fn apply(&self, value: i8) -> i8 {
-        match self {
-            Modifier::Add(x) => value + x,
-            Modifier::Multiply(x) => value * x,
+    #[cfg(test)]
+    pub fn with_effect(self, effect: ModifierEffect) -> Self {
+        Self {
+            effect: Some(effect),
+            ..self
         }
     }
+
+    pub fn is_rolling(&self) -> bool {
+        self.rolling
+    }
+
+    /* Like the null and x2 cards in Gloomhaven, these reset the tray at the end of the round */
+    pub fn resets_tray(&self) -> bool {
+        matches!(self.value, ModifierValue::Multiply(_))
+    }
+
+    /* Damage can go negative in between, e.g. -2 before a rolling x2 */
+    pub fn apply(&self, value: i32) -> i32 {
+        match self.value {
+            ModifierValue::Add(x) => value.saturating_add(x.into()),
+            ModifierValue::Multiply(x) => value.saturating_mul(x.into()),
+        }
+    }
+
+    /// The damage after all of the modifiers in order, before it is clamped to 0
+    pub fn apply_all(modifiers: &[Modifier], damage: usize) -> i32 {
+        let damage = i32::try_from(damage).unwrap_or(i32::MAX);
+        modifiers
+            .iter()
+            .fold(damage, |damage, modifier| modifier.apply(damage))
+    }
+
+    /// The damage after all of the modifiers, it never drops below 0
+    pub fn modified_damage(modifiers: &[Modifier], damage: usize) -> usize {
+        Self::apply_all(modifiers, damage).max(0) as usize
+    }
+}
+
+/* Where the modifiers of a figure come from */
+pub trait ModifierSource {
+    /// How often an attack draws, rolling modifiers drawn on top are not counted
+    fn draws(&self, advantage: AttackAdvantage) -> usize;
+
+    /// The players roll a real die for this source and are asked for the column it showed
+    fn asks_for_column(&self) -> bool;
+
+    /// Only a source that asks for the column is given the one the players rolled
+    fn draw(
+        &mut self,
+        advantage: AttackAdvantage,
+        rolled: Option<ModifierTrayColumn>,
+        rng: &mut ScenarioRng,
+    ) -> Modifier;
+
+    /// Whether this has to be reset at the end of the round
+    fn needs_reset(&self) -> bool;
+
+    fn reset(&mut self, rng: &mut ScenarioRng);
+
+    /* Everything a draw or reset changes, kept by the commands for undo */
+    fn state(&self) -> ModifierSourceState;
+
+    fn restore(&mut self, state: ModifierSourceState);
+}
+
+#[derive(Debug, Clone, Reflect)]
+pub enum ModifierSourceState {
+    Tray(ModifierTrayPosition),
+    Deck(ModifierDeck),
 }
 
-#[derive(Debug, Reflect)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
 pub enum ModifierTrayColumn {
     Minus,
     Neutral,
@@ -76,16 +233,50 @@ pub enum ModifierTrayColumn {
 
 impl ModifierTrayColumn {
     const LEN: usize = ModifierTrayColumn::Last as usize;
+
+    pub const ALL: [ModifierTrayColumn; Self::LEN] = [
+        ModifierTrayColumn::Minus,
+        ModifierTrayColumn::Neutral,
+        ModifierTrayColumn::Plus,
+    ];
+
+    /// The column actually used for a roll of this column
+    pub fn shifted(self, advantage: AttackAdvantage) -> Self {
+        let column = self as usize;
+        let column = match advantage {
+            AttackAdvantage::Normal => column,
+            AttackAdvantage::Advantage => (column + 1).min(Self::LEN - 1),
+            AttackAdvantage::Disadvantage => column.saturating_sub(1),
+        };
+
+        Self::ALL[column]
+    }
 }
 
-#[derive(Debug, Component, Reflect)]
+#[derive(Debug, Clone, Component, Reflect, Deserialize)]
 #[component(on_add = ModifierTray::on_add, on_remove = ModifierTray::on_remove)]
 pub struct ModifierTray {
     id: FigureId,
+    #[serde(default)]
     active_row: usize,
+    /* Rows of the table in the order they are rolled on */
+    #[serde(default = "ModifierTray::unshuffled")]
+    order: [usize; ModifierTray::LEN],
+    #[serde(default)]
+    reset_at_end_of_round: bool,
+    /* The players roll their own die instead of the ScenarioRng */
+    #[serde(default)]
+    physical_die: bool,
     table: [[Modifier; ModifierTrayColumn::LEN]; ModifierTray::LEN],
 }
 
+#[derive(Debug, Clone, Copy, Reflect)]
+pub struct ModifierTrayPosition {
+    active_row: usize,
+    order: [usize; ModifierTray::LEN],
+    reset_at_end_of_round: bool,
+}
+
 impl ModifierTray {
     const LEN: usize = 6;
 
@@ -94,45 +285,208 @@ impl ModifierTray {
             id,
             table,
             active_row: 0,
+            order: Self::unshuffled(),
+            reset_at_end_of_round: false,
+            physical_die: false,
         }
     }
 
+    fn unshuffled() -> [usize; Self::LEN] {
+        std::array::from_fn(|row| row)
+    }
+
+    pub fn id(&self) -> FigureId {
+        self.id
+    }
+
     pub fn get(&self, column: ModifierTrayColumn) -> Modifier {
-        self.table[self.active_row][column as usize]
+        self.table[self.order[self.active_row]][column as usize]
     }
 
-    pub fn next_row(&mut self) {
+    /// Advance to the next row, the rows are shuffled once all of them were rolled on
+    pub fn next_row(&mut self, rng: &mut ScenarioRng) {
         self.active_row = (self.active_row + 1) % Self::LEN;
+        if self.active_row == 0 {
+            rng.shuffle(&mut self.order);
+        }
     }
 
     fn on_add(mut world: DeferredWorld, entity: Entity, _: ComponentId) {
         let id = world.get::<ModifierTray>(entity).unwrap().id;
-        let mut modifier_trays = world.get_resource_mut::<ModifierTrays>().unwrap();
+        let mut modifier_sources = world.get_resource_mut::<ModifierSources>().unwrap();
 
-        modifier_trays.0.insert(id, entity);
+        modifier_sources.0.insert(id.into(), entity);
     }
 
     fn on_remove(mut world: DeferredWorld, entity: Entity, _: ComponentId) {
         let id = world.get::<ModifierTray>(entity).unwrap().id;
-        let mut modifier_trays = world.get_resource_mut::<ModifierTrays>().unwrap();
+        let mut modifier_sources = world.get_resource_mut::<ModifierSources>().unwrap();
 
-        modifier_trays.0.remove(&id);
+        modifier_sources.0.remove(&ModifierSourceOwner::from(id));
     }
 }
 
+/* The tray handles advantage by shifting columns, so it is always rolled only once */
+impl ModifierSource for ModifierTray {
+    fn draws(&self, _advantage: AttackAdvantage) -> usize {
+        1
+    }
+
+    fn asks_for_column(&self) -> bool {
+        self.physical_die
+    }
+
+    fn draw(
+        &mut self,
+        advantage: AttackAdvantage,
+        rolled: Option<ModifierTrayColumn>,
+        rng: &mut ScenarioRng,
+    ) -> Modifier {
+        /* Throw the die to pick the column, unless the players already did */
+        let roll = rolled
+            .unwrap_or_else(|| ModifierTrayColumn::ALL[rng.gen_range(0..ModifierTrayColumn::LEN)]);
+        let modifier = self.get(roll.shifted(advantage));
+        if modifier.resets_tray() {
+            self.reset_at_end_of_round = true;
+        }
+        self.next_row(rng);
+
+        modifier
+    }
+
+    fn needs_reset(&self) -> bool {
+        self.reset_at_end_of_round
+    }
+
+    /* Shuffle the rows and start over on the first one */
+    fn reset(&mut self, rng: &mut ScenarioRng) {
+        self.active_row = 0;
+        self.reset_at_end_of_round = false;
+        rng.shuffle(&mut self.order);
+    }
+
+    fn state(&self) -> ModifierSourceState {
+        ModifierSourceState::Tray(ModifierTrayPosition {
+            active_row: self.active_row,
+            order: self.order,
+            reset_at_end_of_round: self.reset_at_end_of_round,
+        })
+    }
+
+    fn restore(&mut self, state: ModifierSourceState) {
+        let ModifierSourceState::Tray(position) = state else {
+            panic!("A ModifierTray can only be restored from a tray position");
+        };
+
+        self.active_row = position.active_row;
+        self.order = position.order;
+        self.reset_at_end_of_round = position.reset_at_end_of_round;
+    }
+}
+
+/* Who draws from a modifier source, scenario files write Figure((1)) or Monsters */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect, Deserialize)]
+pub enum ModifierSourceOwner {
+    Figure(FigureId),
+    /* The deck all monsters share in Gloomhaven */
+    Monsters,
+}
+
+impl From<FigureId> for ModifierSourceOwner {
+    fn from(id: FigureId) -> Self {
+        Self::Figure(id)
+    }
+}
+
+/* Trays and decks of the running scenario by the owner that uses them */
 #[derive(Debug, Default, Resource, Reflect)]
-pub struct ModifierTrays(HashMap<FigureId, Entity>);
+pub struct ModifierSources(HashMap<ModifierSourceOwner, Entity>);
+
+impl ModifierSources {
+    pub fn get(&self, owner: &ModifierSourceOwner) -> Option<Entity> {
+        self.0.get(owner).copied()
+    }
+}
+
+/// The owner of the modifier source the figure draws from, a source of its own goes first
+pub fn modifier_source_owner(world: &World, entity: Entity) -> Option<ModifierSourceOwner> {
+    let modifier_sources = world.get_resource::<ModifierSources>()?;
+    let owner = ModifierSourceOwner::from(*world.get::<FigureId>(entity)?);
+    if modifier_sources.get(&owner).is_some() {
+        return Some(owner);
+    }
+
+    let is_monster = world.get::<Team>(entity) == Some(&Team::Monster);
+    (is_monster
+        && modifier_sources
+            .get(&ModifierSourceOwner::Monsters)
+            .is_some())
+    .then_some(ModifierSourceOwner::Monsters)
+}
+
+/// The modifier source the figure draws from, if there is one in this scenario
+pub fn modifier_source(world: &World, entity: Entity) -> Option<&dyn ModifierSource> {
+    let owner = modifier_source_owner(world, entity)?;
+    let source = world.resource::<ModifierSources>().get(&owner)?;
+
+    if let Some(modifier_tray) = world.get::<ModifierTray>(source) {
+        return Some(modifier_tray);
+    }
+    world
+        .get::<ModifierDeck>(source)
+        .map(|modifier_deck| modifier_deck as &dyn ModifierSource)
+}
 
-impl ModifierTrays {
-    pub fn get(&self, id: &FigureId) -> Option<Entity> {
-        self.0.get(id).copied()
+pub fn reset_modifier_sources(
+    mut command_queue: ResMut<ScenarioCommandQueue>,
+    modifier_trays: Query<&ModifierTray>,
+    modifier_decks: Query<&ModifierDeck>,
+) {
+    let owners = modifier_trays
+        .iter()
+        .filter(|modifier_tray| modifier_tray.needs_reset())
+        .map(|modifier_tray| modifier_tray.id().into())
+        .chain(
+            modifier_decks
+                .iter()
+                .filter(|modifier_deck| modifier_deck.needs_reset())
+                .map(ModifierDeck::owner),
+        );
+
+    for owner in owners {
+        command_queue.queue(vec![ResetModifierSourceCommand::new(owner).into()]);
     }
 }
 
+/* Draws and resets change the modifier source and the rng together */
+/* Returns None if the owner has no modifier source in this scenario */
+fn modifier_source_scope<R>(
+    world: &mut World,
+    owner: ModifierSourceOwner,
+    f: impl FnOnce(&mut dyn ModifierSource, &mut ScenarioRng) -> R,
+) -> Option<R> {
+    let source = world.resource::<ModifierSources>().get(&owner)?;
+
+    world.resource_scope(|world, mut rng: Mut<ScenarioRng>| {
+        let mut source = world.get_entity_mut(source).ok()?;
+        if let Some(mut modifier_tray) = source.get_mut::<ModifierTray>() {
+            return Some(f(&mut *modifier_tray, &mut rng));
+        }
+        let mut modifier_deck = source.get_mut::<ModifierDeck>()?;
+        Some(f(&mut *modifier_deck, &mut rng))
+    })
+}
+
+/* Rolling modifiers queue up another RollModifierCommand for the same draw */
 #[derive(Debug, Clone, Reflect)]
 pub struct RollModifierCommand {
     entity: Entity,
-    previous_row: Option<usize>,
+    advantage: AttackAdvantage,
+    /* The answer of the players if they roll a real die */
+    column: Option<ModifierTrayColumn>,
+    owner: Option<ModifierSourceOwner>,
+    previous_state: Option<ModifierSourceState>,
+    previous_rng: Option<u64>,
     modifier: Option<Modifier>,
 }
 
@@ -140,11 +494,19 @@ impl RollModifierCommand {
     pub fn new(entity: Entity) -> Self {
         Self {
             entity,
-            previous_row: None,
+            advantage: AttackAdvantage::Normal,
+            column: None,
+            owner: None,
+            previous_state: None,
+            previous_rng: None,
             modifier: None,
         }
     }
 
+    pub fn with_advantage(self, advantage: AttackAdvantage) -> Self {
+        Self { advantage, ..self }
+    }
+
     pub fn modifier(&self) -> Option<Modifier> {
         self.modifier
     }
@@ -152,38 +514,299 @@ impl RollModifierCommand {
 
 impl ScenarioCommandTrait for RollModifierCommand {
     fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
-        let modifier_trays = world.get_resource::<ModifierTrays>().unwrap();
-        let figure_id = world.get::<FigureId>(self.entity).unwrap();
-        let modifier_tray_entity = modifier_trays.get(figure_id).unwrap();
+        let Some(owner) = modifier_source_owner(world, self.entity) else {
+            /* Nothing to draw from, so nothing changes the attack */
+            debug!("{} has no modifier source to draw from", self.entity);
+            self.modifier = Some(Modifier::zero());
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        };
 
-        /* TODO: Randomly throw dice */
-        let column = ModifierTrayColumn::Neutral;
+        let asks_for_column = modifier_source(world, self.entity)
+            .is_some_and(|modifier_source| modifier_source.asks_for_column());
+        if asks_for_column && self.column.is_none() {
+            return ScenarionCommandExecuteResult::Pending(InputRequest::new(
+                format!("Which column did the die show for {}?", self.entity),
+                ModifierTrayColumn::ALL
+                    .iter()
+                    .map(|column| format!("{:?}", column))
+                    .collect(),
+            ));
+        }
 
-        let mut modifier_tray = world.get_mut::<ModifierTray>(modifier_tray_entity).unwrap();
-        self.previous_row = Some(modifier_tray.active_row);
+        let (previous_state, previous_rng, modifier) =
+            modifier_source_scope(world, owner, |modifier_source, rng| {
+                let previous_state = modifier_source.state();
+                let previous_rng = rng.state();
+                let modifier = modifier_source.draw(self.advantage, self.column, rng);
 
-        let modifier = modifier_tray.get(column);
-        modifier_tray.next_row();
+                (previous_state, previous_rng, modifier)
+            })
+            .unwrap();
 
+        self.owner = Some(owner);
+        self.previous_state = Some(previous_state);
+        self.previous_rng = Some(previous_rng);
         self.modifier = Some(modifier);
 
+        if modifier.is_rolling() {
+            let roll = RollModifierCommand::new(self.entity).with_advantage(self.advantage);
+            return ScenarionCommandExecuteResult::Done(vec![roll.into()]);
+        }
         ScenarionCommandExecuteResult::Done(vec![])
     }
 
     fn undo(self, world: &mut World) -> ScenarioCommand {
-        let modifier_trays = world.get_resource::<ModifierTrays>().unwrap();
-        let figure_id = world.get::<FigureId>(self.entity).unwrap();
-        let modifier_tray_entity = modifier_trays.get(figure_id).unwrap();
+        /* A neutral draw without modifier source changed nothing */
+        if let Some(owner) = self.owner {
+            modifier_source_scope(world, owner, |modifier_source, rng| {
+                modifier_source.restore(self.previous_state.clone().unwrap());
+                rng.restore(self.previous_rng.unwrap());
+            });
+        }
 
-        let mut modifier_tray = world.get_mut::<ModifierTray>(modifier_tray_entity).unwrap();
+        let command = Self {
+            column: None,
+            owner: None,
+            previous_state: None,
+            previous_rng: None,
+            modifier: None,
+            ..self
+        };
+        command.into()
+    }
 
-        modifier_tray.active_row = self.previous_row.unwrap();
+    fn input(&mut self, choice: usize) {
+        self.column = ModifierTrayColumn::ALL.get(choice).copied();
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+/* Modifier sources are identified by their owner, they are respawned with new entities when loading */
+#[derive(Debug, Clone, Reflect)]
+pub struct ResetModifierSourceCommand {
+    owner: ModifierSourceOwner,
+    previous_state: Option<ModifierSourceState>,
+    previous_rng: Option<u64>,
+}
+
+impl ResetModifierSourceCommand {
+    pub fn new(owner: impl Into<ModifierSourceOwner>) -> Self {
+        Self {
+            owner: owner.into(),
+            previous_state: None,
+            previous_rng: None,
+        }
+    }
+}
+
+impl ScenarioCommandTrait for ResetModifierSourceCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let previous = modifier_source_scope(world, self.owner, |modifier_source, rng| {
+            let previous = (modifier_source.state(), rng.state());
+            modifier_source.reset(rng);
+
+            previous
+        });
+
+        match previous {
+            Some((previous_state, previous_rng)) => {
+                self.previous_state = Some(previous_state);
+                self.previous_rng = Some(previous_rng);
+            }
+            None => debug!("{:?} has no modifier source to reset", self.owner),
+        }
+
+        ScenarionCommandExecuteResult::Done(vec![])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        if let Some(previous_state) = self.previous_state.clone() {
+            modifier_source_scope(world, self.owner, |modifier_source, rng| {
+                modifier_source.restore(previous_state);
+                rng.restore(self.previous_rng.unwrap());
+            });
+        }
 
         let command = Self {
-            previous_row: None,
-            modifier: None,
+            previous_state: None,
+            previous_rng: None,
             ..self
         };
         command.into()
     }
+
+    fn map_entities(&mut self, _entity_mapper: &mut dyn EntityMapper) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::figure::{
+        attack::{ApplyAttackCommand, Attack},
+        condition::Conditions,
+        health::Health,
+    };
+
+    use super::*;
+
+    /* Every column of a row holds the same modifier, so the row tells which one was rolled */
+    fn spawn_tray(world: &mut World) -> Entity {
+        world.init_resource::<ModifierSources>();
+        world.insert_resource(ScenarioRng::new(0));
+        world.spawn(ModifierTray::new(
+            FigureId::new(0),
+            std::array::from_fn(|row| [Modifier::add(row as i8); ModifierTrayColumn::LEN]),
+        ));
+        world.spawn(FigureId::new(0)).id()
+    }
+
+    #[test]
+    fn advantage_shifts_columns() {
+        let neutral = ModifierTrayColumn::Neutral;
+
+        assert_eq!(
+            neutral.shifted(AttackAdvantage::Advantage),
+            ModifierTrayColumn::Plus
+        );
+        assert_eq!(
+            ModifierTrayColumn::Plus.shifted(AttackAdvantage::Advantage),
+            ModifierTrayColumn::Plus
+        );
+        assert_eq!(
+            ModifierTrayColumn::Minus.shifted(AttackAdvantage::Disadvantage),
+            ModifierTrayColumn::Minus
+        );
+    }
+
+    #[test]
+    fn rows_wrap_and_undo_restores_the_tray() {
+        let mut world = World::new();
+        let entity = spawn_tray(&mut world);
+
+        let rolls: Vec<RollModifierCommand> = (0..ModifierTray::LEN)
+            .map(|_| {
+                let mut command = RollModifierCommand::new(entity);
+                command.execute(&mut world);
+                command
+            })
+            .collect();
+        let modifiers: Vec<Modifier> = rolls.iter().map(|roll| roll.modifier.unwrap()).collect();
+        assert_eq!(
+            modifiers,
+            (0..ModifierTray::LEN as i8)
+                .map(Modifier::add)
+                .collect::<Vec<_>>()
+        );
+
+        let first_roll = rolls.into_iter().next().unwrap();
+        first_roll.undo(&mut world);
+        let modifier_tray = world.query::<&ModifierTray>().single(&world);
+        assert_eq!(modifier_tray.active_row, 0);
+        assert_eq!(modifier_tray.order, ModifierTray::unshuffled());
+        assert_eq!(world.resource::<ScenarioRng>().state(), 0);
+    }
+
+    #[test]
+    fn multiply_resets_tray() {
+        let mut world = World::new();
+        let entity = spawn_tray(&mut world);
+        world
+            .query::<&mut ModifierTray>()
+            .single_mut(&mut world)
+            .table[0] = [Modifier::miss(); ModifierTrayColumn::LEN];
+
+        RollModifierCommand::new(entity).execute(&mut world);
+        assert!(world.query::<&ModifierTray>().single(&world).needs_reset());
+
+        ResetModifierSourceCommand::new(FigureId::new(0)).execute(&mut world);
+        let modifier_tray = world.query::<&ModifierTray>().single(&world);
+        assert_eq!(modifier_tray.active_row, 0);
+        assert!(!modifier_tray.needs_reset());
+    }
+
+    #[test]
+    fn plain_values_and_whole_modifiers_deserialize() {
+        let modifiers: Vec<Modifier> = bevy::scene::ron::from_str(
+            "[Add(1), Multiply(2), Modifier(value: Add(0), rolling: true, effect: Some(Push(1)))]",
+        )
+        .unwrap();
+
+        assert_eq!(
+            modifiers,
+            vec![
+                Modifier::plus_one(),
+                Modifier::crit(),
+                Modifier::rolling(0).with_effect(ModifierEffect::Push(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn players_are_asked_for_the_column_of_their_die() {
+        let mut world = World::new();
+        let entity = spawn_tray(&mut world);
+        let mut modifier_tray = world.query::<&mut ModifierTray>().single_mut(&mut world);
+        modifier_tray.physical_die = true;
+        modifier_tray.table[0] = [
+            Modifier::minus_one(),
+            Modifier::zero(),
+            Modifier::plus_one(),
+        ];
+
+        let mut command = RollModifierCommand::new(entity);
+        assert!(matches!(
+            command.execute(&mut world),
+            ScenarionCommandExecuteResult::Pending(_)
+        ));
+
+        command.input(2);
+        command.execute(&mut world);
+        assert_eq!(command.modifier(), Some(Modifier::plus_one()));
+        /* The rng is left alone, the players rolled */
+        assert_eq!(world.resource::<ScenarioRng>().state(), 0);
+    }
+
+    #[test]
+    fn attacks_draw_nothing_until_the_die_is_read() {
+        let mut world = World::new();
+        let entity = spawn_tray(&mut world);
+        world
+            .query::<&mut ModifierTray>()
+            .single_mut(&mut world)
+            .physical_die = true;
+        let target = world.spawn((Health::new(5), Conditions::new(&[]))).id();
+
+        let attack = Attack::new(target, 2);
+        let mut command = ApplyAttackCommand::new(entity, attack, AttackAdvantage::Normal);
+        assert!(matches!(
+            command.execute(&mut world),
+            ScenarionCommandExecuteResult::Pending(_)
+        ));
+        assert_eq!(world.query::<&ModifierTray>().single(&world).active_row, 0);
+
+        command.input(1);
+        command.execute(&mut world);
+        assert_eq!(command.drawn(), &[Modifier::add(0)]);
+        assert_eq!(command.final_damage(), Some(2));
+    }
+
+    #[test]
+    fn attacks_without_modifier_source_are_not_modified() {
+        let mut world = World::new();
+        world.init_resource::<ModifierSources>();
+        world.insert_resource(ScenarioRng::new(0));
+        let entity = world.spawn(FigureId::new(0)).id();
+        let target = world.spawn((Health::new(5), Conditions::new(&[]))).id();
+
+        let attack = Attack::new(target, 2);
+        let mut command = ApplyAttackCommand::new(entity, attack, AttackAdvantage::Advantage);
+        command.execute(&mut world);
+        assert_eq!(command.drawn(), &[Modifier::zero()]);
+        assert_eq!(command.final_damage(), Some(2));
+
+        command.undo(&mut world);
+        assert_eq!(world.resource::<ScenarioRng>().state(), 0);
+    }
 }
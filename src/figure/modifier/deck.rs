@@ -0,0 +1,423 @@
+use bevy::{
+    ecs::{component::ComponentId, entity::EntityMapper, world::DeferredWorld},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::{
+    figure::attack::AttackAdvantage,
+    scenario::{
+        command::{ScenarioCommand, ScenarioCommandTrait, ScenarionCommandExecuteResult},
+        rng::ScenarioRng,
+    },
+};
+
+use super::{
+    modifier_source_owner, Modifier, ModifierSource, ModifierSourceOwner, ModifierSourceState,
+    ModifierSources, ModifierTrayColumn,
+};
+
+/*
+    Gloomhaven draws modifiers as cards from a deck:
+    - cards are drawn at random from the draw pile, which is the same as drawing from a shuffled one
+    - drawn cards go to the discard pile, which is shuffled back in once the draw pile is empty
+    - drawing the standard null or x2 card shuffles the discard pile back in at the end of the round
+    - rolling modifiers are drawn together with the cards after them
+    - advantage and disadvantage draw twice
+    - Bless and Curse are shuffled in and leave the deck again once drawn
+    - there are only 10 Bless and 10 Curse cards, a deck holding all of them gets no more
+*/
+
+const MAX_BLESS_OR_CURSE: usize = 10;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect, Deserialize)]
+pub enum ModifierCardKind {
+    #[default]
+    Standard,
+    Bless,
+    Curse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, Deserialize)]
+pub struct ModifierCard {
+    pub modifier: Modifier,
+    #[serde(default)]
+    pub kind: ModifierCardKind,
+}
+
+impl ModifierCard {
+    pub fn new(modifier: Modifier) -> Self {
+        Self {
+            modifier,
+            kind: ModifierCardKind::Standard,
+        }
+    }
+
+    pub fn bless() -> Self {
+        Self {
+            modifier: Modifier::crit(),
+            kind: ModifierCardKind::Bless,
+        }
+    }
+
+    pub fn curse() -> Self {
+        Self {
+            modifier: Modifier::miss(),
+            kind: ModifierCardKind::Curse,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Component, Reflect, Deserialize)]
+#[component(on_add = ModifierDeck::on_add, on_remove = ModifierDeck::on_remove)]
+pub struct ModifierDeck {
+    owner: ModifierSourceOwner,
+    #[serde(rename = "cards")]
+    draw_pile: Vec<ModifierCard>,
+    #[serde(default)]
+    discard_pile: Vec<ModifierCard>,
+    #[serde(default)]
+    reshuffle_at_end_of_round: bool,
+}
+
+impl ModifierDeck {
+    pub fn new(owner: impl Into<ModifierSourceOwner>, modifiers: Vec<Modifier>) -> Self {
+        Self {
+            owner: owner.into(),
+            draw_pile: modifiers.into_iter().map(ModifierCard::new).collect(),
+            discard_pile: vec![],
+            reshuffle_at_end_of_round: false,
+        }
+    }
+
+    /// The 20 cards every character and monster type starts with
+    pub fn standard(owner: impl Into<ModifierSourceOwner>) -> Self {
+        let mut modifiers = vec![];
+        modifiers.extend([Modifier::zero(); 6]);
+        modifiers.extend([Modifier::plus_one(); 5]);
+        modifiers.extend([Modifier::minus_one(); 5]);
+        modifiers.extend([
+            Modifier::plus_two(),
+            Modifier::minus_two(),
+            Modifier::miss(),
+            Modifier::crit(),
+        ]);
+
+        Self::new(owner, modifiers)
+    }
+
+    pub fn owner(&self) -> ModifierSourceOwner {
+        self.owner
+    }
+
+    /// Shuffle a card into the draw pile
+    pub fn insert(&mut self, card: ModifierCard) {
+        self.draw_pile.push(card);
+    }
+
+    pub fn count(&self, kind: ModifierCardKind) -> usize {
+        self.draw_pile
+            .iter()
+            .chain(&self.discard_pile)
+            .filter(|card| card.kind == kind)
+            .count()
+    }
+
+    fn reshuffle(&mut self) {
+        self.draw_pile.append(&mut self.discard_pile);
+        self.reshuffle_at_end_of_round = false;
+    }
+
+    fn on_add(mut world: DeferredWorld, entity: Entity, _: ComponentId) {
+        let owner = world.get::<ModifierDeck>(entity).unwrap().owner;
+        let mut modifier_sources = world.get_resource_mut::<ModifierSources>().unwrap();
+
+        modifier_sources.0.insert(owner, entity);
+    }
+
+    fn on_remove(mut world: DeferredWorld, entity: Entity, _: ComponentId) {
+        let owner = world.get::<ModifierDeck>(entity).unwrap().owner;
+        let mut modifier_sources = world.get_resource_mut::<ModifierSources>().unwrap();
+
+        modifier_sources.0.remove(&owner);
+    }
+}
+
+impl ModifierSource for ModifierDeck {
+    fn draws(&self, advantage: AttackAdvantage) -> usize {
+        advantage.draws()
+    }
+
+    fn asks_for_column(&self) -> bool {
+        false
+    }
+
+    fn draw(
+        &mut self,
+        _advantage: AttackAdvantage,
+        _rolled: Option<ModifierTrayColumn>,
+        rng: &mut ScenarioRng,
+    ) -> Modifier {
+        if self.draw_pile.is_empty() {
+            self.reshuffle();
+        }
+        /* Only a deck without any cards at all gets here, it has nothing to change the attack */
+        if self.draw_pile.is_empty() {
+            debug!("{:?} has no modifier cards to draw", self.owner);
+            return Modifier::zero();
+        }
+
+        let card = self
+            .draw_pile
+            .remove(rng.gen_range(0..self.draw_pile.len()));
+        if card.kind == ModifierCardKind::Standard {
+            if card.modifier.resets_tray() {
+                self.reshuffle_at_end_of_round = true;
+            }
+            self.discard_pile.push(card);
+        }
+
+        card.modifier
+    }
+
+    fn needs_reset(&self) -> bool {
+        self.reshuffle_at_end_of_round
+    }
+
+    fn reset(&mut self, _rng: &mut ScenarioRng) {
+        self.reshuffle();
+    }
+
+    fn state(&self) -> ModifierSourceState {
+        ModifierSourceState::Deck(self.clone())
+    }
+
+    fn restore(&mut self, state: ModifierSourceState) {
+        let ModifierSourceState::Deck(deck) = state else {
+            panic!("A ModifierDeck can only be restored from a deck");
+        };
+
+        *self = deck;
+    }
+}
+
+/* Bless and Curse go into the deck the figure draws from, figures rolling on a tray can not get them */
+#[derive(Debug, Clone, Reflect)]
+pub struct AddModifierCardCommand {
+    entity: Entity,
+    card: ModifierCard,
+    inserted: Option<bool>,
+}
+
+impl AddModifierCardCommand {
+    pub fn new(entity: Entity, card: ModifierCard) -> Self {
+        Self {
+            entity,
+            card,
+            inserted: None,
+        }
+    }
+
+    fn modifier_deck(world: &mut World, entity: Entity) -> Option<Mut<'_, ModifierDeck>> {
+        let owner = modifier_source_owner(world, entity)?;
+        let source = world.resource::<ModifierSources>().get(&owner)?;
+
+        world.get_mut::<ModifierDeck>(source)
+    }
+}
+
+impl ScenarioCommandTrait for AddModifierCardCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let modifier_deck = Self::modifier_deck(world, self.entity);
+        let limited = self.card.kind != ModifierCardKind::Standard;
+
+        let inserted = match modifier_deck {
+            Some(modifier_deck)
+                if limited && modifier_deck.count(self.card.kind) >= MAX_BLESS_OR_CURSE =>
+            {
+                debug!("{} has no {:?} cards left", self.entity, self.card.kind);
+                false
+            }
+            Some(mut modifier_deck) => {
+                modifier_deck.insert(self.card);
+                true
+            }
+            None => {
+                debug!("{} has no modifier deck for {:?}", self.entity, self.card);
+                false
+            }
+        };
+        self.inserted = Some(inserted);
+
+        ScenarionCommandExecuteResult::Done(vec![])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        /* Anything drawn since is already undone, so the card is still on top of the draw pile */
+        if self.inserted.unwrap() {
+            Self::modifier_deck(world, self.entity)
+                .unwrap()
+                .draw_pile
+                .pop();
+        }
+
+        let command = Self {
+            inserted: None,
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        figure::{modifier::RollModifierCommand, FigureId, Team},
+        scenario::command::ScenarioCommandQueue,
+    };
+
+    use super::*;
+
+    fn draw_all(modifier_deck: &mut ModifierDeck, rng: &mut ScenarioRng) -> Vec<Modifier> {
+        (0..modifier_deck.draw_pile.len())
+            .map(|_| modifier_deck.draw(AttackAdvantage::Normal, None, rng))
+            .collect()
+    }
+
+    #[test]
+    fn drawing_empties_the_draw_pile_once() {
+        let mut rng = ScenarioRng::new(0);
+        let mut modifier_deck = ModifierDeck::standard(FigureId::new(0));
+
+        let mut drawn = draw_all(&mut modifier_deck, &mut rng);
+        drawn.sort_by_key(|modifier| format!("{:?}", modifier));
+        let mut cards = ModifierDeck::standard(FigureId::new(0))
+            .draw_pile
+            .into_iter()
+            .map(|card| card.modifier)
+            .collect::<Vec<_>>();
+        cards.sort_by_key(|modifier| format!("{:?}", modifier));
+
+        assert_eq!(drawn, cards);
+        assert!(modifier_deck.needs_reset());
+
+        modifier_deck.reset(&mut rng);
+        assert_eq!(modifier_deck.draw_pile.len(), 20);
+        assert!(!modifier_deck.needs_reset());
+    }
+
+    #[test]
+    fn bless_and_curse_leave_once_drawn() {
+        let mut rng = ScenarioRng::new(0);
+        let mut modifier_deck = ModifierDeck::new(FigureId::new(0), vec![Modifier::zero()]);
+        modifier_deck.insert(ModifierCard::bless());
+        modifier_deck.insert(ModifierCard::curse());
+
+        draw_all(&mut modifier_deck, &mut rng);
+
+        assert_eq!(modifier_deck.count(ModifierCardKind::Bless), 0);
+        assert_eq!(modifier_deck.count(ModifierCardKind::Curse), 0);
+        assert_eq!(
+            modifier_deck.discard_pile,
+            vec![ModifierCard::new(Modifier::zero())]
+        );
+        /* Neither of them triggers a reshuffle */
+        assert!(!modifier_deck.needs_reset());
+    }
+
+    #[test]
+    fn empty_deck_draws_nothing() {
+        let mut rng = ScenarioRng::new(0);
+        let mut modifier_deck = ModifierDeck::new(FigureId::new(0), vec![]);
+
+        assert_eq!(
+            modifier_deck.draw(AttackAdvantage::Normal, None, &mut rng),
+            Modifier::zero()
+        );
+    }
+
+    #[test]
+    fn undo_takes_the_added_card_out_again() {
+        let mut world = World::new();
+        world.init_resource::<ModifierSources>();
+        world.insert_resource(ScenarioRng::new(0));
+        let modifier_deck = ModifierDeck::new(
+            FigureId::new(0),
+            vec![
+                Modifier::zero(),
+                Modifier::plus_one(),
+                Modifier::minus_one(),
+            ],
+        );
+        world.spawn(modifier_deck.clone());
+        let entity = world.spawn(FigureId::new(0)).id();
+        let deck = |world: &mut World| world.query::<&ModifierDeck>().single(world).clone();
+
+        /* The draws are undone first, which leaves the Curse on top for its own undo to pop */
+        let mut command_queue = ScenarioCommandQueue::default();
+        command_queue.queue(vec![
+            AddModifierCardCommand::new(entity, ModifierCard::curse()).into(),
+            RollModifierCommand::new(entity).into(),
+            RollModifierCommand::new(entity).into(),
+        ]);
+        command_queue.execute_all(&mut world);
+        assert_eq!(deck(&mut world).draw_pile.len(), 2);
+
+        for _ in 0..3 {
+            command_queue.undo(&mut world);
+        }
+        assert_eq!(deck(&mut world).draw_pile, modifier_deck.draw_pile);
+        assert_eq!(deck(&mut world).count(ModifierCardKind::Curse), 0);
+    }
+
+    #[test]
+    fn monsters_share_a_deck_of_at_most_ten_curses() {
+        let mut world = World::new();
+        world.init_resource::<ModifierSources>();
+        world.insert_resource(ScenarioRng::new(0));
+        world.spawn(ModifierDeck::standard(FigureId::new(0)));
+        world.spawn(ModifierDeck::standard(ModifierSourceOwner::Monsters));
+        let character = world.spawn((FigureId::new(0), Team::Player)).id();
+        let monsters = [1, 2].map(|id| world.spawn((FigureId::new(id), Team::Monster)).id());
+
+        let mut command_queue = ScenarioCommandQueue::default();
+        for _ in 0..6 {
+            command_queue.queue(
+                monsters
+                    .iter()
+                    .map(|monster| AddModifierCardCommand::new(*monster, ModifierCard::curse()))
+                    .map(Into::into)
+                    .collect(),
+            );
+        }
+        command_queue.queue(vec![AddModifierCardCommand::new(
+            character,
+            ModifierCard::curse(),
+        )
+        .into()]);
+        command_queue.execute_all(&mut world);
+
+        let curses = |world: &mut World, owner| {
+            world
+                .query::<&ModifierDeck>()
+                .iter(world)
+                .find(|modifier_deck| modifier_deck.owner() == owner)
+                .unwrap()
+                .count(ModifierCardKind::Curse)
+        };
+        assert_eq!(curses(&mut world, ModifierSourceOwner::Monsters), 10);
+        assert_eq!(curses(&mut world, FigureId::new(0).into()), 1);
+
+        /* Undoing the two Curses that did not fit leaves the ten in the deck alone */
+        for _ in 0..3 {
+            command_queue.undo_transaction(&mut world);
+        }
+        assert_eq!(curses(&mut world, ModifierSourceOwner::Monsters), 10);
+        command_queue.undo_transaction(&mut world);
+        assert_eq!(curses(&mut world, ModifierSourceOwner::Monsters), 9);
+    }
+}
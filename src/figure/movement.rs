@@ -1,9 +1,16 @@
-use bevy::prelude::*;
+use bevy::{ecs::entity::EntityMapper, prelude::*};
 use hexx::Hex;
 
 use crate::scenario::{
     command::{ScenarioCommand, ScenarioCommandTrait, ScenarionCommandExecuteResult},
-    map::{HexGrid, HexPosition},
+    input::InputRequest,
+    map::{HexGrid, HexLayer, HexPosition, OverlayKind},
+    pathfinding::{MovementMap, Path},
+};
+
+use super::{
+    health::{DamageSource, Health, SufferDamageCommand},
+    Team,
 };
 
 #[derive(Debug, Default, Clone, Copy, Reflect)]
@@ -19,31 +26,97 @@ pub struct MoveCommand {
     entity: Entity,
     start: Option<Hex>,
     end: Hex,
+    /* Movement points to spend, a path that costs more is not taken */
+    movement: u32,
     kind: MovementKind,
+    /* The AI first tries to avoid traps and hazardous terrain, the path has to match its decision */
+    negative_is_obstacle: bool,
 }
 
 impl MoveCommand {
-    pub fn new(entity: Entity, hex: Hex) -> Self {
+    pub fn new(entity: Entity, hex: Hex, movement: u32) -> Self {
         Self {
             entity,
             end: hex,
+            movement,
             start: Default::default(),
             kind: Default::default(),
+            negative_is_obstacle: false,
         }
     }
 
-    pub fn _with_kind(mut self, kind: MovementKind) -> Self {
+    pub fn with_kind(mut self, kind: MovementKind) -> Self {
         self.kind = kind;
         self
     }
+
+    pub fn with_negative_is_obstacle(self, negative_is_obstacle: bool) -> Self {
+        Self {
+            negative_is_obstacle,
+            ..self
+        }
+    }
+}
+
+/// Find the path a figure would take to end its movement on the given hex
+/// None if it can not get there at all, the cost still has to be checked against its movement
+pub fn find_move_path(
+    world: &World,
+    entity: Entity,
+    end: Hex,
+    kind: MovementKind,
+    negative_is_obstacle: bool,
+) -> Option<Path> {
+    let hex_grid = world.get::<Parent>(entity)?.get();
+    let hex_grid = world.get::<HexGrid>(hex_grid)?;
+    let start = world.get::<HexPosition>(entity)?.hex();
+    let team = *world.get::<Team>(entity)?;
+
+    MovementMap::from_hex_grid(hex_grid, world).find_path(
+        start,
+        end,
+        team,
+        kind,
+        negative_is_obstacle,
+    )
 }
 
 impl ScenarioCommandTrait for MoveCommand {
     fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let Some(path) = find_move_path(
+            world,
+            self.entity,
+            self.end,
+            self.kind,
+            self.negative_is_obstacle,
+        ) else {
+            debug!("{} can not move to {:?}", self.entity, self.end);
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        };
+        if path.cost > self.movement {
+            debug!(
+                "{} needs {} movement to get to {:?}, but only has {}",
+                self.entity, path.cost, self.end, self.movement
+            );
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        }
+
         let hex_grid = {
             let parent = world.get::<Parent>(self.entity).unwrap();
             parent.get()
         };
+
+        /* Flying passes over everything, jumping only lands on the last hex */
+        let entered = &path.hexes[1..];
+        let entered = match self.kind {
+            MovementKind::Default => entered,
+            MovementKind::Jump => &entered[entered.len().saturating_sub(1)..],
+            MovementKind::Fly => &[],
+        };
+        let movement_map =
+            MovementMap::from_hex_grid(world.get::<HexGrid>(hex_grid).unwrap(), world);
+        let follow_ups = trigger_overlays(&movement_map, self.entity, entered);
+
         let [mut hex_grid, mut hex_position] =
             world.get_entity_mut([hex_grid, self.entity]).unwrap();
 
@@ -53,13 +126,20 @@ impl ScenarioCommandTrait for MoveCommand {
         self.start = Some(hex_position.hex());
         hex_position.update(self.end, self.entity, &mut hex_grid);
 
-        println!("Move {} to {:?}", self.entity, self.end);
+        debug!(
+            "Move {} to {:?} for {} movement",
+            self.entity, self.end, path.cost
+        );
 
-        /* Reactivity how? Via an event that is consumed and someone adds to the queue? */
-        ScenarionCommandExecuteResult::Done(vec![])
+        ScenarionCommandExecuteResult::Done(follow_ups)
     }
 
     fn undo(self, world: &mut World) -> ScenarioCommand {
+        /* Nothing was moved if there was no path */
+        let Some(start) = self.start else {
+            return self.into();
+        };
+
         let hex_grid = {
             let parent = world.get::<Parent>(self.entity).unwrap();
             parent.get()
@@ -70,12 +150,551 @@ impl ScenarioCommandTrait for MoveCommand {
         let mut hex_grid = hex_grid.get_mut::<HexGrid>().unwrap();
         let mut hex_position = hex_position.get_mut::<HexPosition>().unwrap();
 
-        hex_position.update(self.start.unwrap(), self.entity, &mut hex_grid);
+        hex_position.update(start, self.entity, &mut hex_grid);
+
+        let command = Self {
+            start: None,
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum ForcedMovementKind {
+    Push,
+    Pull,
+}
+
+/* Damage of traps and hazardous terrain at scenario level 0, hazardous terrain deals half */
+const TRAP_DAMAGE: usize = 2;
+const HAZARDOUS_TERRAIN_DAMAGE: usize = 1;
+
+/// Every trap and hazardous terrain the figure entered, in the order it entered them
+fn trigger_overlays(
+    movement_map: &MovementMap,
+    entity: Entity,
+    hexes: &[Hex],
+) -> Vec<ScenarioCommand> {
+    hexes
+        .iter()
+        .filter_map(|hex| match movement_map.overlay(hex) {
+            Some(OverlayKind::Trap) => Some(SpringTrapCommand::new(entity, *hex).into()),
+            Some(OverlayKind::HazardousTerrain) => Some(
+                SufferDamageCommand::new(
+                    DamageSource::Overlay(OverlayKind::HazardousTerrain),
+                    entity,
+                    HAZARDOUS_TERRAIN_DAMAGE,
+                )
+                .into(),
+            ),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The hexes a figure is pushed or pulled through, each one a step away from or towards the origin
+/// It stops early at walls, obstacles and other figures, choices pick among several options step by step
+/// Err holds the options of the first step with more than one and no valid choice for it yet,
+/// together with the number of choices used up to there
+pub fn forced_movement_path(
+    movement_map: &MovementMap,
+    start: Hex,
+    origin: Hex,
+    kind: ForcedMovementKind,
+    distance: u32,
+    choices: &[usize],
+) -> Result<Vec<Hex>, (Vec<Hex>, usize)> {
+    let mut hexes = vec![];
+    let mut chosen = 0;
+    let mut current = start;
+
+    for _ in 0..distance {
+        let current_distance = current.unsigned_distance_to(origin);
+        let options: Vec<Hex> = current
+            .all_neighbors()
+            .into_iter()
+            .filter(|hex| {
+                let hex_distance = hex.unsigned_distance_to(origin);
+                let direction = match kind {
+                    ForcedMovementKind::Push => hex_distance > current_distance,
+                    ForcedMovementKind::Pull => hex_distance < current_distance,
+                };
+
+                direction
+                    && movement_map.contains(hex)
+                    && movement_map.figure(hex).is_none()
+                    && movement_map.overlay(hex) != Some(OverlayKind::Obstacle)
+            })
+            .collect();
+
+        let next = match options.len() {
+            0 => break,
+            1 => options[0],
+            _ => match choices.get(chosen).and_then(|choice| options.get(*choice)) {
+                Some(hex) => {
+                    chosen += 1;
+                    *hex
+                }
+                None => return Err((options, chosen)),
+            },
+        };
+        hexes.push(next);
+        current = next;
+    }
+
+    Ok(hexes)
+}
+
+/* Push and pull move the target away from or towards the figure forcing it, this is no movement of its own */
+/* Every trap and hazardous terrain on the way is triggered all the same */
+#[derive(Debug, Clone, Reflect)]
+pub struct ForcedMovementCommand {
+    source: Entity,
+    entity: Entity,
+    kind: ForcedMovementKind,
+    distance: u32,
+    /* The hex picked whenever there are several, the players pick for their figures */
+    choices: Vec<usize>,
+    start: Option<Hex>,
+}
+
+impl ForcedMovementCommand {
+    pub fn new(source: Entity, entity: Entity, kind: ForcedMovementKind, distance: u32) -> Self {
+        Self {
+            source,
+            entity,
+            kind,
+            distance,
+            choices: Default::default(),
+            start: Default::default(),
+        }
+    }
+}
+
+impl ScenarioCommandTrait for ForcedMovementCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        /* A dead target stays where it died */
+        if world.get::<Health>(self.entity).is_none_or(Health::is_dead) {
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        }
+
+        let hex_grid = world.get::<Parent>(self.entity).unwrap().get();
+        let (Some(origin), Some(start)) = (
+            world.get::<HexPosition>(self.source),
+            world.get::<HexPosition>(self.entity),
+        ) else {
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        };
+        let (origin, start) = (origin.hex(), start.hex());
+        /* TODO: Monsters should pick by their own rules, for now they take the first option */
+        let asks = world
+            .get::<Team>(self.source)
+            .is_some_and(|team| *team != Team::Monster);
+
+        let movement_map =
+            MovementMap::from_hex_grid(world.get::<HexGrid>(hex_grid).unwrap(), world);
+        let hexes = loop {
+            match forced_movement_path(
+                &movement_map,
+                start,
+                origin,
+                self.kind,
+                self.distance,
+                &self.choices,
+            ) {
+                Ok(hexes) => break hexes,
+                Err((options, chosen)) => {
+                    /* An answer that is none of the options is asked for again */
+                    self.choices.truncate(chosen);
+                    if asks {
+                        return ScenarionCommandExecuteResult::Pending(InputRequest::new(
+                            format!("{:?} {} to which hex?", self.kind, self.entity),
+                            options.iter().map(|hex| format!("{:?}", hex)).collect(),
+                        ));
+                    }
+                    self.choices.push(0);
+                }
+            }
+        };
+        let Some(end) = hexes.last().copied() else {
+            debug!("{} can not be {:?}ed", self.entity, self.kind);
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        };
+
+        let follow_ups = trigger_overlays(&movement_map, self.entity, &hexes);
+
+        let [mut hex_grid, mut hex_position] =
+            world.get_entity_mut([hex_grid, self.entity]).unwrap();
+
+        let mut hex_grid = hex_grid.get_mut::<HexGrid>().unwrap();
+        let mut hex_position = hex_position.get_mut::<HexPosition>().unwrap();
+
+        self.start = Some(start);
+        hex_position.update(end, self.entity, &mut hex_grid);
+
+        debug!(
+            "{:?} {} to {:?} over {} hexes",
+            self.kind,
+            self.entity,
+            end,
+            hexes.len()
+        );
+
+        ScenarionCommandExecuteResult::Done(follow_ups)
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        /* Nothing was moved if there was no free hex */
+        let Some(start) = self.start else {
+            return Self {
+                choices: vec![],
+                ..self
+            }
+            .into();
+        };
+
+        let hex_grid = world.get::<Parent>(self.entity).unwrap().get();
+        let [mut hex_grid, mut hex_position] =
+            world.get_entity_mut([hex_grid, self.entity]).unwrap();
+
+        let mut hex_grid = hex_grid.get_mut::<HexGrid>().unwrap();
+        let mut hex_position = hex_position.get_mut::<HexPosition>().unwrap();
+
+        hex_position.update(start, self.entity, &mut hex_grid);
 
         let command = Self {
+            choices: vec![],
             start: None,
             ..self
         };
         command.into()
     }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.source = entity_mapper.map_entity(self.source);
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+
+    fn input(&mut self, choice: usize) {
+        self.choices.push(choice);
+    }
+}
+
+/* A trap damages the figure that sprung it and is gone afterwards */
+/* Undo puts a new trap on the hex, entities of overlays are not kept around */
+#[derive(Debug, Clone, Reflect)]
+pub struct SpringTrapCommand {
+    entity: Entity,
+    hex: Hex,
+    sprung: bool,
+}
+
+impl SpringTrapCommand {
+    pub fn new(entity: Entity, hex: Hex) -> Self {
+        Self {
+            entity,
+            hex,
+            sprung: false,
+        }
+    }
+}
+
+impl ScenarioCommandTrait for SpringTrapCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let hex_grid = world.get::<Parent>(self.entity).unwrap().get();
+        let Some(trap) = world
+            .get::<HexGrid>(hex_grid)
+            .unwrap()
+            .get(&self.hex, &HexLayer::Overlay)
+            .filter(|overlay| world.get::<OverlayKind>(*overlay) == Some(&OverlayKind::Trap))
+        else {
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        };
+
+        despawn_with_children_recursive(world, trap, true);
+        self.sprung = true;
+        debug!("{} springs the trap on {:?}", self.entity, self.hex);
+
+        ScenarionCommandExecuteResult::Done(vec![SufferDamageCommand::new(
+            DamageSource::Overlay(OverlayKind::Trap),
+            self.entity,
+            TRAP_DAMAGE,
+        )
+        .into()])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        if self.sprung {
+            let hex_grid = world.get::<Parent>(self.entity).unwrap().get();
+            let trap = world
+                .spawn((
+                    HexPosition::new(self.hex, HexLayer::Overlay),
+                    OverlayKind::Trap,
+                ))
+                .set_parent(hex_grid)
+                .id();
+            world
+                .get_mut::<HexGrid>(hex_grid)
+                .unwrap()
+                .insert(self.hex, &HexLayer::Overlay, trap);
+        }
+
+        let command = Self {
+            sprung: false,
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        figure::{FigureId, FigureInstance},
+        scenario::{command::ScenarioCommandQueue, notation::parse_hex_map},
+    };
+
+    use super::*;
+
+    fn movement_map(radius: u32) -> MovementMap {
+        let mut movement_map = MovementMap::default();
+        for hex in Hex::ZERO.range(radius) {
+            movement_map.insert_hex(hex);
+        }
+
+        movement_map
+    }
+
+    #[test]
+    fn push_moves_away_until_blocked() {
+        let mut movement_map = movement_map(2);
+        let origin = Hex::new(-1, 0);
+
+        let hexes = forced_movement_path(
+            &movement_map,
+            Hex::ZERO,
+            origin,
+            ForcedMovementKind::Push,
+            3,
+            &[0; 3],
+        )
+        .unwrap();
+        assert_eq!(hexes.len(), 2);
+        assert!(hexes.windows(2).all(
+            |step| step[1].unsigned_distance_to(origin) > step[0].unsigned_distance_to(origin)
+        ));
+
+        for hex in Hex::ZERO.all_neighbors() {
+            if hex.unsigned_distance_to(origin) > 1 {
+                movement_map.insert_figure(hex, Team::Monster);
+            }
+        }
+        assert!(forced_movement_path(
+            &movement_map,
+            Hex::ZERO,
+            origin,
+            ForcedMovementKind::Push,
+            1,
+            &[]
+        )
+        .unwrap()
+        .is_empty());
+    }
+
+    #[test]
+    fn pull_stops_next_to_the_origin() {
+        let mut movement_map = movement_map(3);
+        let origin = Hex::new(-3, 0);
+        movement_map.insert_figure(origin, Team::Player);
+
+        let hexes = forced_movement_path(
+            &movement_map,
+            Hex::new(1, 0),
+            origin,
+            ForcedMovementKind::Pull,
+            5,
+            &[0; 5],
+        )
+        .unwrap();
+
+        assert_eq!(hexes.len(), 3);
+        assert_eq!(hexes.last().unwrap().unsigned_distance_to(origin), 1);
+    }
+
+    #[test]
+    fn move_is_rejected_beyond_its_movement() {
+        let mut world = World::new();
+        let figures = parse_hex_map("P0  D   .")
+            .unwrap()
+            .spawn(&mut world.commands());
+        world.flush();
+        let entity = figures[&(FigureId::new(0), FigureInstance::new(0))];
+        let hex = |world: &World| world.get::<HexPosition>(entity).unwrap().hex();
+
+        /* Difficult terrain costs 2 on the way */
+        MoveCommand::new(entity, Hex::new(2, 0), 2).execute(&mut world);
+        assert_eq!(hex(&world), Hex::ZERO);
+
+        MoveCommand::new(entity, Hex::new(2, 0), 3).execute(&mut world);
+        assert_eq!(hex(&world), Hex::new(2, 0));
+    }
+
+    #[test]
+    fn move_avoiding_negative_hexes_walks_around_traps() {
+        let mut world = World::new();
+        let figures = parse_hex_map(
+            "\
+M0  T   .
+  .   .",
+        )
+        .unwrap()
+        .spawn(&mut world.commands());
+        world.flush();
+        let entity = figures[&(FigureId::new(0), FigureInstance::new(0))];
+        let hex = |world: &World| world.get::<HexPosition>(entity).unwrap().hex();
+
+        /* Around the trap takes 3 movement */
+        MoveCommand::new(entity, Hex::new(2, 0), 2)
+            .with_negative_is_obstacle(true)
+            .execute(&mut world);
+        assert_eq!(hex(&world), Hex::ZERO);
+
+        MoveCommand::new(entity, Hex::new(2, 0), 3)
+            .with_negative_is_obstacle(true)
+            .execute(&mut world);
+        assert_eq!(hex(&world), Hex::new(2, 0));
+    }
+
+    fn overlay(world: &mut World, hex: Hex) -> Option<OverlayKind> {
+        let hex_grid = world.query::<&HexGrid>().single(world);
+        let overlay = hex_grid.get(&hex, &HexLayer::Overlay)?;
+        world.get::<OverlayKind>(overlay).copied()
+    }
+
+    #[test]
+    fn moving_through_negative_hexes_triggers_them() {
+        let mut world = World::new();
+        let figures = parse_hex_map("M0  T   H   T")
+            .unwrap()
+            .spawn(&mut world.commands());
+        world.flush();
+        let monster = figures[&(FigureId::new(0), FigureInstance::new(0))];
+        let health = |world: &World| world.get::<Health>(monster).unwrap().current();
+        let full_health = health(&world);
+        let move_to_the_end = |world: &mut World, kind| {
+            let mut command_queue = ScenarioCommandQueue::default();
+            command_queue.queue(vec![MoveCommand::new(monster, Hex::new(3, 0), 3)
+                .with_kind(kind)
+                .into()]);
+            command_queue.execute_all(world);
+            let damage = full_health - health(world);
+            let sprung = [1, 3].map(|x| overlay(world, Hex::new(x, 0)).is_none());
+            command_queue.undo_transaction(world);
+
+            (damage, sprung)
+        };
+
+        assert_eq!(
+            move_to_the_end(&mut world, MovementKind::Default),
+            (2 * TRAP_DAMAGE + HAZARDOUS_TERRAIN_DAMAGE, [true, true])
+        );
+        assert_eq!(
+            move_to_the_end(&mut world, MovementKind::Jump),
+            (TRAP_DAMAGE, [false, true])
+        );
+        assert_eq!(
+            move_to_the_end(&mut world, MovementKind::Fly),
+            (0, [false, false])
+        );
+
+        /* Undo put the traps back where they were */
+        assert_eq!(health(&world), full_health);
+        assert_eq!(overlay(&mut world, Hex::new(1, 0)), Some(OverlayKind::Trap));
+        assert_eq!(overlay(&mut world, Hex::new(3, 0)), Some(OverlayKind::Trap));
+    }
+
+    #[test]
+    fn push_onto_a_trap_triggers_it() {
+        let mut world = World::new();
+        let figures = parse_hex_map("P0  M1  T   H")
+            .unwrap()
+            .spawn(&mut world.commands());
+        world.flush();
+        let player = figures[&(FigureId::new(0), FigureInstance::new(0))];
+        let monster = figures[&(FigureId::new(1), FigureInstance::new(0))];
+        let health = |world: &World| world.get::<Health>(monster).unwrap().current();
+        let full_health = health(&world);
+
+        let mut command_queue = ScenarioCommandQueue::default();
+        command_queue.queue(vec![ForcedMovementCommand::new(
+            player,
+            monster,
+            ForcedMovementKind::Push,
+            2,
+        )
+        .into()]);
+        command_queue.execute_all(&mut world);
+        assert_eq!(
+            world.get::<HexPosition>(monster).unwrap().hex(),
+            Hex::new(3, 0)
+        );
+        assert_eq!(
+            health(&world),
+            full_health - TRAP_DAMAGE - HAZARDOUS_TERRAIN_DAMAGE
+        );
+        assert_eq!(overlay(&mut world, Hex::new(2, 0)), None);
+
+        command_queue.undo_transaction(&mut world);
+        assert_eq!(
+            world.get::<HexPosition>(monster).unwrap().hex(),
+            Hex::new(1, 0)
+        );
+        assert_eq!(health(&world), full_health);
+        assert_eq!(overlay(&mut world, Hex::new(2, 0)), Some(OverlayKind::Trap));
+    }
+
+    #[test]
+    fn players_choose_where_to_push() {
+        let mut world = World::new();
+        let figures = parse_hex_map(
+            "\
+P0  M1  .
+  .   .",
+        )
+        .unwrap()
+        .spawn(&mut world.commands());
+        world.flush();
+        let player = figures[&(FigureId::new(0), FigureInstance::new(0))];
+        let monster = figures[&(FigureId::new(1), FigureInstance::new(0))];
+
+        let mut command = ForcedMovementCommand::new(player, monster, ForcedMovementKind::Push, 1);
+        let ScenarionCommandExecuteResult::Pending(input_request) = command.execute(&mut world)
+        else {
+            panic!("A push with two free hexes should ask the player");
+        };
+        assert_eq!(input_request.options().len(), 2);
+
+        /* Anything but one of the options asks again */
+        command.input(2);
+        assert!(matches!(
+            command.execute(&mut world),
+            ScenarionCommandExecuteResult::Pending(_)
+        ));
+
+        command.input(1);
+        command.execute(&mut world);
+        assert_eq!(
+            format!("{:?}", world.get::<HexPosition>(monster).unwrap().hex()),
+            input_request.options()[1]
+        );
+    }
 }
@@ -1,5 +1,27 @@
-use bevy::prelude::*;
-use bevy_inspector_egui::quick::StateInspectorPlugin;
+use bevy::{
+    ecs::entity::{EntityMapper, MapEntities},
+    prelude::*,
+    state::state::StateTransitionSteps,
+    utils::HashMap,
+};
+
+use crate::{
+    figure::{
+        ai::{Initiative, MonsterAbilities},
+        condition::ExpireConditionsCommand,
+        health::Health,
+        FigureId, FigureInstance, Initiatives, MonsterRank, Summon, Team,
+    },
+    scenario::{
+        command::{
+            ScenarioCommand, ScenarioCommandQueue, ScenarioCommandTrait,
+            ScenarionCommandExecuteResult,
+        },
+        definition::ScenarioSpawned,
+        input::InputRequest,
+        setup::{ScenarioGoal, ScenarioSetup},
+    },
+};
 
 pub struct GamePlugin;
 
@@ -12,19 +34,42 @@ impl Plugin for GamePlugin {
         app.register_type::<AppState>()
             .register_type::<ScenarioState>()
             .register_type::<RoundState>()
-            .register_type::<Round>();
+            .register_type::<Round>()
+            .register_type::<TurnOrder>()
+            .register_type::<RemovedTurn>()
+            .register_type::<StartOfTurn>()
+            .register_type::<EndOfTurn>();
+        app.init_resource::<TurnOrder>();
+
+        app.add_event::<StartOfTurn>().add_event::<EndOfTurn>();
+        /* Read at the end of a scenario, also when no definition is ever loaded */
+        app.add_event::<ScenarioSpawned>();
+
+        app.add_systems(
+            StateTransition,
+            finish_restoring_save.after(StateTransitionSteps::EnterSchedules),
+        );
 
         AppState::setup(app);
         ScenarioState::setup(app);
         RoundState::setup(app);
-
-        app.add_plugins(StateInspectorPlugin::<AppState>::default());
-        app.add_plugins(StateInspectorPlugin::<ScenarioState>::default());
-        app.add_plugins(StateInspectorPlugin::<RoundState>::default());
-        app.add_plugins(MeshPickingPlugin);
     }
 }
 
+/* Inserted by loading a save, the states it restores already ran their OnEnter effects before saving */
+#[derive(Debug, Resource)]
+pub struct RestoringSave;
+
+/// Run condition for OnEnter effects that must not happen again when a save is loaded
+pub fn not_restoring_save(restoring_save: Option<Res<RestoringSave>>) -> bool {
+    restoring_save.is_none()
+}
+
+/* The restored states are entered by now */
+fn finish_restoring_save(mut commands: Commands) {
+    commands.remove_resource::<RestoringSave>();
+}
+
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States, Reflect)]
 pub enum AppState {
     #[default]
@@ -70,20 +115,32 @@ impl ScenarioState {
     }
 }
 
+/* Debug option to move on without waiting for the current scenario state to finish */
+fn force_advance_scenario(keyboard_input: &ButtonInput<KeyCode>) -> bool {
+    keyboard_input.just_pressed(KeyCode::KeyS)
+}
+
+/* Debug option to move on without waiting for the current round state to finish */
+fn force_advance_round(keyboard_input: &ButtonInput<KeyCode>) -> bool {
+    keyboard_input.just_pressed(KeyCode::Space)
+}
+
 fn begin_transition(
     mut next_state: ResMut<NextState<ScenarioState>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    scenario_setup: Option<Res<ScenarioSetup>>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::KeyS) {
+    if force_advance_scenario(&keyboard_input) || scenario_setup.is_some() {
         next_state.set(ScenarioState::Play);
     }
 }
 
+/* The scenario ends with the round its goals are met in, see end_of_round_transition */
 fn play_transition(
     mut next_state: ResMut<NextState<ScenarioState>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::KeyS) {
+    if force_advance_scenario(&keyboard_input) {
         next_state.set(ScenarioState::End);
     }
 }
@@ -91,8 +148,12 @@ fn play_transition(
 fn end_transition(
     mut next_state: ResMut<NextState<ScenarioState>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut scenario_spawned: EventReader<ScenarioSpawned>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::KeyS) {
+    /* Another scenario or a fresh copy of this one was spawned */
+    let spawned = scenario_spawned.read().count() > 0;
+
+    if force_advance_scenario(&keyboard_input) || spawned {
         next_state.set(ScenarioState::Begin);
     }
 }
@@ -112,10 +173,9 @@ pub enum RoundState {
 impl RoundState {
     fn setup(app: &mut App) -> &mut App {
         app.add_systems(OnEnter(RoundState::Init), init_on_enter)
-            .add_systems(Update, init_transition.run_if(in_state(RoundState::Init)))
             .add_systems(
                 OnEnter(RoundState::StartOfRoundEffects),
-                start_of_round_effects_on_enter,
+                start_of_round_effects_on_enter.run_if(not_restoring_save),
             )
             .add_systems(
                 Update,
@@ -125,15 +185,28 @@ impl RoundState {
                 Update,
                 card_selection_transition.run_if(in_state(RoundState::CardSelection)),
             )
+            .add_systems(
+                OnEnter(RoundState::OrderingInitiative),
+                ordering_initiative_on_enter.run_if(not_restoring_save),
+            )
             .add_systems(
                 Update,
                 ordering_initiative_transition.run_if(in_state(RoundState::OrderingInitiative)),
             )
+            .add_systems(
+                OnEnter(RoundState::CharacterAndMonsterTurns),
+                character_and_monster_turns_on_enter.run_if(not_restoring_save),
+            )
             .add_systems(
                 Update,
-                character_and_monster_turns_transition
+                (end_character_turn, character_and_monster_turns_transition)
+                    .chain()
                     .run_if(in_state(RoundState::CharacterAndMonsterTurns)),
             )
+            .add_systems(
+                OnEnter(RoundState::EndOfRound),
+                end_of_round_on_enter.run_if(not_restoring_save),
+            )
             .add_systems(
                 Update,
                 end_of_round_transition.run_if(in_state(RoundState::EndOfRound)),
@@ -141,72 +214,246 @@ impl RoundState {
     }
 }
 
-#[derive(Debug, Default, Resource, Reflect)]
+#[derive(Debug, Default, Clone, Resource, Reflect)]
 #[reflect(Resource)]
-pub struct Round(usize);
+pub struct Round(pub usize);
 
-fn init_on_enter(mut commands: Commands) {
+fn init_on_enter(mut commands: Commands, mut next_state: ResMut<NextState<RoundState>>) {
     commands.insert_resource(Round::default());
-}
-
-fn init_transition(
-    mut next_state: ResMut<NextState<RoundState>>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
-        next_state.set(RoundState::StartOfRoundEffects);
-    }
+    next_state.set(RoundState::StartOfRoundEffects);
 }
 
 fn start_of_round_effects_on_enter(mut round: ResMut<Round>) {
     round.0 += 1;
 }
 
+/* Start of round effects are queued up as commands, they are resolved once the queue is idle */
 fn start_of_round_effects_transition(
     mut next_state: ResMut<NextState<RoundState>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    command_queue: Res<ScenarioCommandQueue>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
+    if force_advance_round(&keyboard_input) || command_queue.is_idle() {
         next_state.set(RoundState::CardSelection);
     }
 }
 
+/* Every character selects its cards by setting its initiative */
 fn card_selection_transition(
     mut next_state: ResMut<NextState<RoundState>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    initiatives: Res<Initiatives>,
+    figures: Query<(&FigureId, &Team, &Health)>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
+    let selected = figures
+        .iter()
+        .filter(|(_, team, health)| **team == Team::Player && !health.is_dead())
+        .all(|(id, _, _)| initiatives.get(id).is_some());
+
+    if force_advance_round(&keyboard_input) || selected {
         next_state.set(RoundState::OrderingInitiative);
     }
 }
 
+type TurnOrderQueryData = (
+    Entity,
+    &'static FigureId,
+    &'static FigureInstance,
+    &'static Team,
+    Option<&'static MonsterRank>,
+    Has<Summon>,
+    &'static Health,
+);
+
+/* Characters with the same initiative act in the order the players decide */
+fn ordering_initiative_on_enter(
+    mut command_queue: ResMut<ScenarioCommandQueue>,
+    initiatives: Res<Initiatives>,
+    figures: Query<(&FigureId, &Team, &Health), Without<Summon>>,
+) {
+    let mut tied: HashMap<u8, Vec<FigureId>> = HashMap::default();
+    for (id, _, _) in figures
+        .iter()
+        .filter(|(_, team, health)| **team == Team::Player && !health.is_dead())
+    {
+        if let Some(initiative) = initiatives.get(id) {
+            tied.entry(initiative).or_default().push(*id);
+        }
+    }
+
+    let mut tied: Vec<(u8, Vec<FigureId>)> =
+        tied.into_iter().filter(|(_, ids)| ids.len() > 1).collect();
+    tied.sort_by_key(|(initiative, _)| *initiative);
+    for (_, mut ids) in tied {
+        ids.sort_by_key(|id| id.id());
+        command_queue.queue_transaction(vec![TiebreakCommand::new(ids).into()]);
+    }
+}
+
+/* The turn order is set once the players broke all ties */
 fn ordering_initiative_transition(
+    mut commands: Commands,
     mut next_state: ResMut<NextState<RoundState>>,
+    command_queue: Res<ScenarioCommandQueue>,
+    initiatives: Res<Initiatives>,
+    figures: Query<TurnOrderQueryData>,
+) {
+    if !command_queue.is_idle() {
+        return;
+    }
+
+    let figures = figures
+        .iter()
+        .filter(|(.., health)| !health.is_dead())
+        .map(
+            |(entity, id, instance, team, rank, summon, _)| TurnOrderFigure {
+                entity,
+                id: *id,
+                instance: *instance,
+                team: *team,
+                rank: rank.copied(),
+                summon,
+            },
+        )
+        .collect();
+
+    let turn_order = TurnOrder::new(figures, &initiatives);
+    debug!("Turn order {:?}", turn_order.entities());
+    commands.insert_resource(turn_order);
+    next_state.set(RoundState::CharacterAndMonsterTurns);
+}
+
+fn character_and_monster_turns_on_enter(
+    mut next_state: ResMut<NextState<RoundState>>,
+    mut turn_order: ResMut<TurnOrder>,
+    mut start_of_turn: EventWriter<StartOfTurn>,
+    figures: Query<&Health, With<FigureId>>,
+) {
+    start_next_turn(
+        &mut next_state,
+        &mut turn_order,
+        &mut start_of_turn,
+        &figures,
+    );
+}
+
+/* Debug option for the players to end their character's turn, there are no cards to play yet */
+fn end_character_turn(
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    turn_order: Res<TurnOrder>,
+    mut command_queue: ResMut<ScenarioCommandQueue>,
+    figures: Query<&Team>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
-        next_state.set(RoundState::CharacterAndMonsterTurns);
+    if !keyboard_input.just_pressed(KeyCode::KeyE) {
+        return;
+    }
+    let Some(entity) = turn_order.current() else {
+        return;
+    };
+
+    if figures.get(entity).is_ok_and(|team| *team != Team::Monster) {
+        command_queue.queue(vec![EndTurnCommand::new(entity).into()]);
     }
 }
 
+/* A turn is taken once it was ended and everything queued up for it has been executed */
 fn character_and_monster_turns_transition(
     mut next_state: ResMut<NextState<RoundState>>,
+    mut turn_order: ResMut<TurnOrder>,
+    mut start_of_turn: EventWriter<StartOfTurn>,
+    mut end_of_turn: EventWriter<EndOfTurn>,
+    figures: Query<&Health, With<FigureId>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    command_queue: Res<ScenarioCommandQueue>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
-        next_state.set(RoundState::EndOfRound);
+    let ended = turn_order.ended && command_queue.is_idle();
+    if !force_advance_round(&keyboard_input) && !ended {
+        return;
+    }
+
+    if let Some(entity) = turn_order.current() {
+        debug!("End of turn for {}", entity);
+        end_of_turn.send(EndOfTurn { entity });
     }
+    start_next_turn(
+        &mut next_state,
+        &mut turn_order,
+        &mut start_of_turn,
+        &figures,
+    );
+}
+
+/* Figures that were despawned or died since the initiative was ordered are skipped */
+fn start_next_turn(
+    next_state: &mut NextState<RoundState>,
+    turn_order: &mut TurnOrder,
+    start_of_turn: &mut EventWriter<StartOfTurn>,
+    figures: &Query<&Health, With<FigureId>>,
+) {
+    while let Some(entity) = turn_order.advance() {
+        if figures.get(entity).is_ok_and(|health| !health.is_dead()) {
+            debug!("Start of turn for {}", entity);
+            start_of_turn.send(StartOfTurn { entity });
+            return;
+        }
+    }
+
+    next_state.set(RoundState::EndOfRound);
+}
+
+/* Cards played this round are done, so are their initiatives and the monsters' abilities */
+fn end_of_round_on_enter(
+    mut initiatives: ResMut<Initiatives>,
+    mut monster_abilities: ResMut<MonsterAbilities>,
+) {
+    *initiatives = Initiatives::default();
+    *monster_abilities = MonsterAbilities::default();
 }
 
 fn end_of_round_transition(
     mut next_state: ResMut<NextState<RoundState>>,
+    mut next_scenario_state: ResMut<NextState<ScenarioState>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    command_queue: Res<ScenarioCommandQueue>,
+    round: Res<Round>,
+    scenario_setup: Option<Res<ScenarioSetup>>,
+    figures: Query<(&Team, &Health)>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Space) {
+    if !force_advance_round(&keyboard_input) && !command_queue.is_idle() {
+        return;
+    }
+
+    /* Only the living count towards losing and the goals */
+    let teams: Vec<Team> = figures
+        .iter()
+        .filter(|(_, health)| !health.is_dead())
+        .map(|(team, _)| *team)
+        .collect();
+    let goals = scenario_setup
+        .as_ref()
+        .map(|scenario_setup| scenario_setup.goals.as_slice())
+        .unwrap_or_default();
+    if !teams.contains(&Team::Player) {
+        info!("Scenario lost in round {}", round.0);
+        next_scenario_state.set(ScenarioState::End);
+    } else if goals_met(goals, &round, &teams) {
+        info!("Scenario won in round {}", round.0);
+        next_scenario_state.set(ScenarioState::End);
+    } else {
         next_state.set(RoundState::StartOfRoundEffects);
     }
 }
 
+/* Custom goals are up to the players, they have to end the scenario themselves */
+fn goals_met(goals: &[ScenarioGoal], round: &Round, teams: &[Team]) -> bool {
+    !goals.is_empty()
+        && goals.iter().all(|goal| match goal {
+            ScenarioGoal::KillAllEnemies => !teams.contains(&Team::Monster),
+            ScenarioGoal::SurviveRounds(rounds) => round.0 >= *rounds,
+            ScenarioGoal::Custom(_) => false,
+        })
+}
+
 /* This is fired whenever an entity should take its turn */
 #[derive(Debug, Event, Reflect)]
 pub struct StartOfTurn {
@@ -218,3 +465,609 @@ pub struct StartOfTurn {
 pub struct EndOfTurn {
     pub entity: Entity,
 }
+
+/* Everything about a figure that decides when it acts */
+#[derive(Debug, Clone, Copy)]
+pub struct TurnOrderFigure {
+    pub entity: Entity,
+    pub id: FigureId,
+    pub instance: FigureInstance,
+    pub team: Team,
+    pub rank: Option<MonsterRank>,
+    pub summon: bool,
+}
+
+/* The figures in the order they act this round */
+#[derive(Debug, Default, Clone, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct TurnOrder {
+    entities: Vec<Entity>,
+    /* Index of the figure whose turn it is, None before the first turn */
+    current: Option<usize>,
+    /* Set by the EndTurnCommand of the current figure */
+    ended: bool,
+}
+
+impl TurnOrder {
+    /// Sort figures by the initiative of their FigureId, figures without one do not act
+    /// Ties go to characters before monsters, then as the players decided, then by FigureId
+    /// Within a FigureId summons act first, then elites, then by instance
+    pub fn new(figures: Vec<TurnOrderFigure>, initiatives: &Initiatives) -> Self {
+        let mut ids: Vec<(bool, usize, u32)> = figures
+            .iter()
+            .map(|figure| {
+                (
+                    figure.team == Team::Monster,
+                    initiatives.tiebreak(&figure.id),
+                    figure.id.id(),
+                )
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+
+        let mut figures: Vec<(Initiative, TurnOrderFigure)> = figures
+            .into_iter()
+            .filter_map(|figure| {
+                let Some(initiative) = initiatives.get(&figure.id) else {
+                    debug!("{} has no initiative and does not act", figure.entity);
+                    return None;
+                };
+                let tiebreak = ids
+                    .iter()
+                    .position(|(_, _, id)| *id == figure.id.id())
+                    .unwrap();
+
+                Some((
+                    Initiative::new(initiative as usize, false, tiebreak),
+                    figure,
+                ))
+            })
+            .collect();
+        figures.sort_by_key(|(initiative, figure)| {
+            (
+                *initiative,
+                !figure.summon,
+                figure.rank != Some(MonsterRank::Elite),
+                figure.instance,
+            )
+        });
+
+        Self {
+            entities: figures.iter().map(|(_, figure)| figure.entity).collect(),
+            current: None,
+            ended: false,
+        }
+    }
+
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn current(&self) -> Option<Entity> {
+        self.entities.get(self.current?).copied()
+    }
+
+    /// Move on to the next figure and return it, None once everyone has acted
+    pub fn advance(&mut self) -> Option<Entity> {
+        let next = self.current.map_or(0, |current| current + 1);
+        self.current = Some(next.min(self.entities.len()));
+        self.ended = false;
+
+        self.current()
+    }
+
+    /// Take a killed figure out of the turn order, its turn ends if it is the current one
+    pub fn remove(&mut self, entity: Entity) -> Option<RemovedTurn> {
+        let index = self.entities.iter().position(|turn| *turn == entity)?;
+
+        if self.current == Some(index) {
+            let ended = self.ended;
+            self.ended = true;
+            return Some(RemovedTurn::Ended { ended });
+        }
+
+        self.entities.remove(index);
+        if let Some(current) = self.current.as_mut().filter(|current| **current > index) {
+            *current -= 1;
+        }
+        Some(RemovedTurn::Removed(index))
+    }
+
+    /// Undo remove of the given figure
+    pub fn restore(&mut self, entity: Entity, removed: RemovedTurn) {
+        match removed {
+            RemovedTurn::Removed(index) => {
+                if let Some(current) = self.current.as_mut().filter(|current| **current >= index) {
+                    *current += 1;
+                }
+                self.entities.insert(index, entity);
+            }
+            RemovedTurn::Ended { ended } => self.ended = ended,
+        }
+    }
+}
+
+/* Where a killed figure was in the turn order, so undo can put it back */
+#[derive(Debug, Clone, Copy, Reflect)]
+pub enum RemovedTurn {
+    /* Its turn already passed or is still to come */
+    Removed(usize),
+    /* It died during its own turn, which ends with it */
+    Ended { ended: bool },
+}
+
+impl MapEntities for TurnOrder {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        for entity in &mut self.entities {
+            *entity = entity_mapper.map_entity(*entity);
+        }
+    }
+}
+
+/* The figure is done with its turn, the next one starts once the queue is idle */
+#[derive(Debug, Clone, Reflect)]
+pub struct EndTurnCommand {
+    entity: Entity,
+}
+
+impl EndTurnCommand {
+    pub fn new(entity: Entity) -> Self {
+        Self { entity }
+    }
+}
+
+impl ScenarioCommandTrait for EndTurnCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let mut turn_order = world.resource_mut::<TurnOrder>();
+        if turn_order.current() != Some(self.entity) {
+            return ScenarionCommandExecuteResult::Done(vec![]);
+        }
+        turn_order.ended = true;
+
+        /* Conditions expire as part of the turn, undoing the end of the turn brings them back */
+        ScenarionCommandExecuteResult::Done(vec![ExpireConditionsCommand::new(self.entity).into()])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        let mut turn_order = world.resource_mut::<TurnOrder>();
+        if turn_order.current() == Some(self.entity) {
+            turn_order.ended = false;
+        }
+
+        self.into()
+    }
+
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper) {
+        self.entity = entity_mapper.map_entity(self.entity);
+    }
+}
+
+/* The players pick which of the characters with tied initiatives acts first, one after another */
+#[derive(Debug, Clone, Reflect)]
+pub struct TiebreakCommand {
+    tied: Vec<FigureId>,
+    /* The characters picked so far, in the order they act */
+    order: Vec<FigureId>,
+    previous: Option<Vec<FigureId>>,
+}
+
+impl TiebreakCommand {
+    pub fn new(tied: Vec<FigureId>) -> Self {
+        Self {
+            tied,
+            order: Default::default(),
+            previous: Default::default(),
+        }
+    }
+
+    fn remaining(&self) -> Vec<FigureId> {
+        self.tied
+            .iter()
+            .filter(|id| !self.order.contains(id))
+            .copied()
+            .collect()
+    }
+}
+
+impl ScenarioCommandTrait for TiebreakCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let remaining = self.remaining();
+        if remaining.len() > 1 {
+            return ScenarionCommandExecuteResult::Pending(InputRequest::new(
+                "Who acts first?",
+                remaining
+                    .iter()
+                    .map(|id| format!("Character {}", id.id()))
+                    .collect(),
+            ));
+        }
+        self.order.extend(remaining);
+
+        let mut initiatives = world.resource_mut::<Initiatives>();
+        let previous = initiatives.tiebreaks().to_vec();
+        let tiebreaks = previous
+            .iter()
+            .filter(|id| !self.tied.contains(id))
+            .chain(&self.order)
+            .copied()
+            .collect();
+        initiatives.set_tiebreaks(tiebreaks);
+        self.previous = Some(previous);
+
+        ScenarionCommandExecuteResult::Done(vec![])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        let mut initiatives = world.resource_mut::<Initiatives>();
+        initiatives.set_tiebreaks(self.previous.unwrap());
+
+        let command = Self {
+            order: vec![],
+            previous: None,
+            ..self
+        };
+        command.into()
+    }
+
+    /* Anything but one of the options is asked for again */
+    fn input(&mut self, choice: usize) {
+        if let Some(id) = self.remaining().get(choice) {
+            self.order.push(*id);
+        }
+    }
+
+    fn map_entities(&mut self, _entity_mapper: &mut dyn EntityMapper) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        figure::{
+            condition::{ConditionKind, Conditions},
+            health::SufferDamageCommand,
+        },
+        headless::HeadlessPlugins,
+        scenario::notation::{parse_hex_map, FIGURE_HEALTH},
+    };
+
+    use super::*;
+
+    fn figure(entity: u32, id: u32, instance: u32, team: Team) -> TurnOrderFigure {
+        TurnOrderFigure {
+            entity: Entity::from_raw(entity),
+            id: FigureId::new(id),
+            instance: FigureInstance::new(instance),
+            team,
+            rank: (team == Team::Monster).then_some(MonsterRank::Normal),
+            summon: false,
+        }
+    }
+
+    fn order(figures: Vec<TurnOrderFigure>, initiatives: &Initiatives) -> Vec<u32> {
+        TurnOrder::new(figures, initiatives)
+            .entities()
+            .iter()
+            .map(|entity| entity.index())
+            .collect()
+    }
+
+    #[test]
+    fn lowest_initiative_acts_first() {
+        let mut initiatives = Initiatives::default();
+        initiatives.set(FigureId::new(0), 50);
+        initiatives.set(FigureId::new(1), 12);
+        initiatives.set(FigureId::new(2), 30);
+
+        let figures = vec![
+            figure(0, 0, 0, Team::Player),
+            figure(1, 1, 0, Team::Player),
+            figure(2, 2, 0, Team::Monster),
+        ];
+
+        assert_eq!(order(figures, &initiatives), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn characters_act_before_tied_monsters() {
+        let mut initiatives = Initiatives::default();
+        initiatives.set(FigureId::new(0), 30);
+        initiatives.set(FigureId::new(1), 30);
+
+        let figures = vec![
+            figure(0, 0, 0, Team::Monster),
+            figure(1, 1, 0, Team::Player),
+        ];
+
+        assert_eq!(order(figures, &initiatives), vec![1, 0]);
+    }
+
+    #[test]
+    fn players_decide_tied_characters() {
+        let mut initiatives = Initiatives::default();
+        initiatives.set(FigureId::new(0), 30);
+        initiatives.set(FigureId::new(1), 30);
+        initiatives.set_tiebreaks(vec![FigureId::new(1)]);
+
+        let figures = vec![figure(0, 0, 0, Team::Player), figure(1, 1, 0, Team::Player)];
+
+        assert_eq!(order(figures, &initiatives), vec![1, 0]);
+    }
+
+    #[test]
+    fn monster_type_acts_together_elites_first() {
+        let mut initiatives = Initiatives::default();
+        initiatives.set(FigureId::new(1), 40);
+        initiatives.set(FigureId::new(2), 40);
+
+        let elite = TurnOrderFigure {
+            rank: Some(MonsterRank::Elite),
+            ..figure(2, 1, 2, Team::Monster)
+        };
+        let figures = vec![
+            figure(0, 1, 0, Team::Monster),
+            figure(1, 2, 0, Team::Monster),
+            elite,
+            figure(3, 1, 1, Team::Monster),
+        ];
+
+        assert_eq!(order(figures, &initiatives), vec![2, 0, 3, 1]);
+    }
+
+    #[test]
+    fn summons_act_before_their_summoner() {
+        let mut initiatives = Initiatives::default();
+        initiatives.set(FigureId::new(0), 20);
+        initiatives.set(FigureId::new(1), 20);
+        initiatives.set_tiebreaks(vec![FigureId::new(1), FigureId::new(0)]);
+
+        let summon = TurnOrderFigure {
+            summon: true,
+            ..figure(2, 0, 1, Team::Ally)
+        };
+        let figures = vec![
+            figure(0, 0, 0, Team::Player),
+            figure(1, 1, 0, Team::Player),
+            summon,
+        ];
+
+        assert_eq!(order(figures, &initiatives), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn figures_without_initiative_do_not_act() {
+        let mut initiatives = Initiatives::default();
+        initiatives.set(FigureId::new(0), 20);
+
+        let figures = vec![
+            figure(0, 0, 0, Team::Player),
+            figure(1, 1, 0, Team::Monster),
+        ];
+        let mut turn_order = TurnOrder::new(figures, &initiatives);
+
+        assert_eq!(turn_order.current(), None);
+        assert_eq!(turn_order.advance(), Some(Entity::from_raw(0)));
+        assert_eq!(turn_order.advance(), None);
+        assert_eq!(turn_order.advance(), None);
+    }
+
+    /* Frames of the game, each one executes whatever was queued up before it */
+    fn step(app: &mut App, frames: usize) {
+        for _ in 0..frames {
+            app.world_mut().resource_scope(
+                |world, mut command_queue: Mut<ScenarioCommandQueue>| {
+                    command_queue.execute_all(world);
+                },
+            );
+            app.update();
+        }
+    }
+
+    /* Starts the scenario of the map and plays up to the first turn, figures act in the order given */
+    fn start(setup: ScenarioSetup, order: &[u32]) -> (App, Vec<Entity>) {
+        let mut app = App::new();
+        app.add_plugins(HeadlessPlugins);
+        app.finish();
+        app.cleanup();
+
+        let figures = setup.spawn(&mut app.world_mut().commands());
+        app.world_mut().flush();
+        app.insert_resource(setup);
+        let figures = order
+            .iter()
+            .map(|id| figures[&(FigureId::new(*id), FigureInstance::new(0))])
+            .collect();
+
+        let mut initiatives = app.world_mut().resource_mut::<Initiatives>();
+        for (initiative, id) in (10..).step_by(10).zip(order) {
+            initiatives.set(FigureId::new(*id), initiative);
+        }
+        app.world_mut()
+            .resource_mut::<NextState<AppState>>()
+            .set(AppState::InGame);
+
+        step(&mut app, 10);
+        (app, figures)
+    }
+
+    fn respond(app: &mut App, choice: usize) {
+        app.world_mut()
+            .resource_scope(|world, mut command_queue: Mut<ScenarioCommandQueue>| {
+                command_queue.respond(world, choice);
+            });
+    }
+
+    fn damage(app: &mut App, source: Entity, target: Entity, damage: usize) {
+        app.world_mut()
+            .resource_scope(|world, mut command_queue: Mut<ScenarioCommandQueue>| {
+                command_queue.queue(vec![SufferDamageCommand::new(source, target, damage).into()]);
+                command_queue.execute_all(world);
+                if command_queue.input_request().is_some() {
+                    command_queue.respond(world, 0);
+                }
+            });
+    }
+
+    fn scenario_state(app: &App) -> ScenarioState {
+        *app.world().resource::<State<ScenarioState>>().get()
+    }
+
+    #[test]
+    fn turns_last_until_they_are_ended() {
+        let (mut app, figures) = start(parse_hex_map("P0  .   M1").unwrap(), &[0, 1]);
+        let (player, monster) = (figures[0], figures[1]);
+
+        assert_eq!(
+            *app.world().resource::<State<RoundState>>().get(),
+            RoundState::CharacterAndMonsterTurns
+        );
+        assert_eq!(app.world().resource::<TurnOrder>().current(), Some(player));
+
+        /* The monster has no ability this round, so it ends its turn right away */
+        app.world_mut()
+            .resource_mut::<ScenarioCommandQueue>()
+            .queue(vec![EndTurnCommand::new(player).into()]);
+        step(&mut app, 1);
+        assert_eq!(app.world().resource::<TurnOrder>().current(), Some(monster));
+
+        /* The next round waits for the players to select their cards */
+        step(&mut app, 10);
+        assert_eq!(
+            *app.world().resource::<State<RoundState>>().get(),
+            RoundState::CardSelection
+        );
+        assert_eq!(app.world().resource::<Round>().0, 2);
+    }
+
+    #[test]
+    fn dead_figures_do_not_take_their_turn() {
+        let (mut app, figures) = start(parse_hex_map("P0  M1  M2").unwrap(), &[0, 1, 2]);
+        let (player, dead, monster) = (figures[0], figures[1], figures[2]);
+
+        damage(&mut app, player, dead, FIGURE_HEALTH);
+        app.world_mut()
+            .resource_mut::<ScenarioCommandQueue>()
+            .queue(vec![EndTurnCommand::new(player).into()]);
+        step(&mut app, 1);
+
+        assert_eq!(app.world().resource::<TurnOrder>().current(), Some(monster));
+        assert!(!app
+            .world()
+            .resource::<TurnOrder>()
+            .entities()
+            .contains(&dead));
+    }
+
+    #[test]
+    fn killing_all_enemies_wins() {
+        let mut setup = parse_hex_map("P0  M1").unwrap();
+        setup.goals = vec![ScenarioGoal::KillAllEnemies];
+        let (mut app, figures) = start(setup, &[0, 1]);
+
+        damage(&mut app, figures[0], figures[1], FIGURE_HEALTH);
+        app.world_mut()
+            .resource_mut::<ScenarioCommandQueue>()
+            .queue(vec![EndTurnCommand::new(figures[0]).into()]);
+        step(&mut app, 10);
+
+        assert_eq!(scenario_state(&app), ScenarioState::End);
+    }
+
+    #[test]
+    fn losing_all_characters_loses() {
+        let (mut app, figures) = start(parse_hex_map("P0  M1").unwrap(), &[0, 1]);
+
+        damage(&mut app, figures[1], figures[0], FIGURE_HEALTH);
+        step(&mut app, 10);
+
+        assert_eq!(scenario_state(&app), ScenarioState::End);
+    }
+
+    #[test]
+    fn players_break_ties_between_characters() {
+        let (mut app, figures) = start(parse_hex_map("P0  P1  P2  M3").unwrap(), &[0, 1, 2, 3]);
+        let (player0, player1, player2) = (figures[0], figures[1], figures[2]);
+
+        for player in [player0, player1, player2] {
+            app.world_mut()
+                .resource_mut::<ScenarioCommandQueue>()
+                .queue(vec![EndTurnCommand::new(player).into()]);
+            step(&mut app, 2);
+        }
+        step(&mut app, 10);
+
+        /* The next round all characters play the same initiative */
+        let mut initiatives = app.world_mut().resource_mut::<Initiatives>();
+        for id in 0..3 {
+            initiatives.set(FigureId::new(id), 50);
+        }
+        step(&mut app, 3);
+
+        assert_eq!(
+            *app.world().resource::<State<RoundState>>().get(),
+            RoundState::OrderingInitiative
+        );
+        let input_request = app
+            .world()
+            .resource::<ScenarioCommandQueue>()
+            .input_request()
+            .cloned()
+            .unwrap();
+        assert_eq!(input_request.options().len(), 3);
+
+        /* Out of range choices are asked for again */
+        respond(&mut app, 3);
+        respond(&mut app, 2);
+        respond(&mut app, 0);
+        step(&mut app, 2);
+
+        assert_eq!(
+            *app.world().resource::<State<RoundState>>().get(),
+            RoundState::CharacterAndMonsterTurns
+        );
+        assert_eq!(
+            &app.world().resource::<TurnOrder>().entities()[..3],
+            &[player2, player0, player1]
+        );
+    }
+
+    #[test]
+    fn summons_take_their_turn_before_their_summoner() {
+        let (app, figures) = start(parse_hex_map("P0  S0  M1").unwrap(), &[0, 1]);
+
+        let turn_order = app.world().resource::<TurnOrder>();
+        let summon = turn_order.current().unwrap();
+        assert!(app.world().get::<Summon>(summon).is_some());
+        assert_eq!(&turn_order.entities()[1..], &figures);
+    }
+
+    #[test]
+    fn conditions_expire_with_the_end_of_the_turn() {
+        let (mut app, figures) = start(parse_hex_map("P0  .   M1").unwrap(), &[0, 1]);
+        let player = figures[0];
+        let muddled = |app: &App| {
+            app.world()
+                .get::<Conditions>(player)
+                .unwrap()
+                .has(ConditionKind::Muddle)
+        };
+        app.world_mut()
+            .get_mut::<Conditions>(player)
+            .unwrap()
+            .add_condition(ConditionKind::Muddle);
+
+        app.world_mut()
+            .resource_scope(|world, mut command_queue: Mut<ScenarioCommandQueue>| {
+                command_queue.queue(vec![EndTurnCommand::new(player).into()]);
+                command_queue.execute_all(world);
+            });
+        assert!(!muddled(&app));
+
+        app.world_mut()
+            .resource_scope(|world, mut command_queue: Mut<ScenarioCommandQueue>| {
+                command_queue.undo_transaction(world);
+            });
+        assert!(muddled(&app));
+        assert_eq!(app.world().resource::<TurnOrder>().current(), Some(player));
+    }
+}
@@ -0,0 +1,81 @@
+use bevy::{app::PluginGroupBuilder, prelude::*, state::app::StatesPlugin, utils::HashMap};
+
+use crate::{
+    figure::{FigureId, FigureInstance, FigurePlugin},
+    game::GamePlugin,
+    player::action::ActionPlugin,
+    scenario::{command::CommandPlugin, setup::ScenarioSetup, ScenarioPlugin},
+};
+
+/* Everything needed to simulate a scenario without window, rendering or input devices */
+/* Commands are driven programmatically through ScenarioCommandQueue::scope */
+pub struct HeadlessPlugins;
+
+impl PluginGroup for HeadlessPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add_group(MinimalPlugins)
+            .add(StatesPlugin)
+            /* Keyboard driven systems still expect ButtonInput, but nothing is ever pressed */
+            .add(bevy::input::InputPlugin)
+            .add(GamePlugin)
+            .add(ScenarioPlugin)
+            .add(CommandPlugin)
+            .add(FigurePlugin)
+            .add(ActionPlugin)
+    }
+}
+
+/// An app without window with the scenario spawned, its commands still have to be queued up
+pub fn headless_scenario(
+    scenario_setup: ScenarioSetup,
+) -> (App, HashMap<(FigureId, FigureInstance), Entity>) {
+    let mut app = App::new();
+    app.add_plugins(HeadlessPlugins);
+    app.finish();
+    app.cleanup();
+    app.update();
+
+    let world = app.world_mut();
+    let figures = scenario_setup.spawn(&mut world.commands());
+    world.flush();
+    world.insert_resource(scenario_setup);
+
+    (app, figures)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        demo::{demo_characters, demo_layout, queue_demo_commands, DEMO_SCENARIO_PATH},
+        figure::health::Health,
+        scenario::{command::ScenarioCommandQueue, definition::ScenarioDefinition},
+    };
+
+    use super::*;
+
+    #[test]
+    fn demo_runs_to_the_end() {
+        let bytes = std::fs::read(format!("assets/{}", DEMO_SCENARIO_PATH)).unwrap();
+        let definition = ScenarioDefinition::from_bytes(&bytes).unwrap();
+        let (mut app, figures) =
+            headless_scenario(definition.setup(demo_layout(), demo_characters()));
+
+        ScenarioCommandQueue::scope(app.world_mut(), |command_queue, world| {
+            queue_demo_commands(command_queue, &figures);
+
+            /* The players always suffer the damage and the first option is never a lost card */
+            command_queue.execute_all(world);
+            while command_queue.input_request().is_some() {
+                command_queue.respond(world, 0);
+                command_queue.execute_all(world);
+            }
+
+            assert!(command_queue.is_idle());
+        });
+
+        let monster = figures[&(FigureId::new(1), FigureInstance::new(0))];
+        let health = app.world().get::<Health>(monster).unwrap();
+        assert!(health.current() < health.max());
+    }
+}
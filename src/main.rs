@@ -1,28 +1,80 @@
 mod demo;
 mod figure;
 mod game;
+mod headless;
 mod player;
 mod scenario;
 
 use bevy::prelude::*;
-use bevy_inspector_egui::quick::WorldInspectorPlugin;
-use demo::DemoPlugin;
-use figure::FigurePlugin;
-use game::GamePlugin;
+use bevy_inspector_egui::quick::{StateInspectorPlugin, WorldInspectorPlugin};
+use demo::{demo_characters, demo_layout, queue_demo_commands, DemoPlugin, DEMO_SCENARIO_PATH};
+use figure::{health::Health, FigureId, FigurePlugin};
+use game::{AppState, GamePlugin, RoundState, ScenarioState};
+use headless::headless_scenario;
 use player::action::ActionPlugin;
-use scenario::{command::CommandPlugin, ScenarioPlugin};
+use scenario::{
+    command::{CommandPlugin, ScenarioCommandQueue},
+    definition::{ScenarioDefinition, ScenarioDefinitionPlugin},
+    input::InputPlugin,
+    replay::ReplayPlugin,
+    save::SavePlugin,
+    ScenarioPlugin,
+};
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--headless") {
+        simulate_demo();
+        return;
+    }
+
     App::new()
         .add_plugins((
             DefaultPlugins,
             GamePlugin,
             ScenarioPlugin,
+            ScenarioDefinitionPlugin,
             DemoPlugin,
             CommandPlugin,
+            InputPlugin,
+            ReplayPlugin,
+            SavePlugin,
             FigurePlugin,
             ActionPlugin,
         ))
         .add_plugins(WorldInspectorPlugin::new())
+        .add_plugins(StateInspectorPlugin::<AppState>::default())
+        .add_plugins(StateInspectorPlugin::<ScenarioState>::default())
+        .add_plugins(StateInspectorPlugin::<RoundState>::default())
+        .add_plugins(MeshPickingPlugin)
         .run();
 }
+
+/* Runs the demo scenario to the end without a window and prints the outcome */
+fn simulate_demo() {
+    /* There is no AssetServer without a window, so the definition is read directly */
+    let definition = std::fs::read(format!("assets/{}", DEMO_SCENARIO_PATH))
+        .map_err(Into::into)
+        .and_then(|bytes| ScenarioDefinition::from_bytes(&bytes));
+    let definition = match definition {
+        Ok(definition) => definition,
+        Err(error) => {
+            println!("Could not read {}: {}", DEMO_SCENARIO_PATH, error);
+            return;
+        }
+    };
+    let (mut app, figures) = headless_scenario(definition.setup(demo_layout(), demo_characters()));
+
+    let world = app.world_mut();
+    ScenarioCommandQueue::scope(world, |command_queue, world| {
+        queue_demo_commands(command_queue, &figures);
+        command_queue.execute_all(world);
+
+        if let Some(input_request) = command_queue.input_request() {
+            println!("Waiting for input: {}", input_request.prompt());
+        }
+    });
+
+    for (id, health) in world.query::<(&FigureId, &Health)>().iter(world) {
+        println!("{:?}: {}/{} health", id, health.current(), health.max());
+    }
+}
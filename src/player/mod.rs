@@ -1,5 +1,3 @@
-use bevy::prelude::*;
-
 pub mod action;
 
 /*
@@ -16,15 +14,3 @@ pub mod action;
 /* So it make only sense to make all these areas entities as well, that I parent them to? */
 /* The idea is to handle the layout of the entities under the parent entity here */
 /* But how to handle movement and stuff? */
-
-#[derive(Debug, Component, Reflect)]
-#[require(Transform, Visibility)]
-pub struct Hand;
-
-#[derive(Debug, Component, Reflect)]
-#[require(Transform, Visibility)]
-pub struct DiscardPile;
-
-#[derive(Debug, Component, Reflect)]
-#[require(Transform, Visibility)]
-pub struct LostPile;
@@ -1,21 +1,36 @@
 use std::collections::VecDeque;
 
-use bevy::prelude::*;
+use bevy::{
+    ecs::entity::{EntityMapper, MapEntities},
+    prelude::*,
+};
 use enum_dispatch::enum_dispatch;
 
-use crate::figure::{
-    attack::{ApplyAttackCommand, AttackCommand},
-    condition::{AddConditionCommand, RemoveConditionCommand},
-    health::SufferDamageCommand,
-    modifier::RollModifierCommand,
-    movement::{MoveCommand, MovementKind},
+use crate::{
+    figure::{
+        ai::{MonsterAbility, MonsterActionCommand},
+        attack::{ApplyAttackCommand, AttackCommand, RetaliateCommand},
+        bonus::{AddBonusCommand, ExpireBonusesCommand},
+        condition::{AddConditionCommand, ExpireConditionsCommand, RemoveConditionCommand},
+        health::{HealCommand, KillCommand, SufferDamageCommand},
+        modifier::{deck::AddModifierCardCommand, ResetModifierSourceCommand, RollModifierCommand},
+        movement::{
+            ForcedMovementCommand, ForcedMovementKind, MoveCommand, MovementKind, SpringTrapCommand,
+        },
+    },
+    game::{EndTurnCommand, TiebreakCommand},
+};
+
+use super::{
+    element::{InfuseElementCommand, WaneElementsCommand},
+    input::{InputRequest, InputResponse},
 };
 
 /* Everything that happens in the scenario needs to be recorded (and maybe this is the source of truth?) */
 /* Every "action" needs to be reversible */
 /* E.g. move, attack need enough information to be 2 way */
 /* Do I even want to be able to undo every move? Should I have the possibility? */
-/* It definitely needs to be atomic commands like move, but for redo they should be grouped? */
+/* It definitely needs to be atomic commands like move, but for undo and redo they are grouped into transactions */
 /* Should moving be a group of move commands or should the move command include a list of hexes to move through? */
 /* The problem is that a single hex move might have a reaction that needs processing, which in turn creates another command on the stack */
 
@@ -28,77 +43,355 @@ impl Plugin for CommandPlugin {
             .register_type::<MovementKind>()
             .register_type::<ScenarioCommandQueue>()
             .register_type::<MoveCommand>()
+            .register_type::<ForcedMovementKind>()
+            .register_type::<ForcedMovementCommand>()
+            .register_type::<SpringTrapCommand>()
             .register_type::<AttackCommand>()
+            .register_type::<RetaliateCommand>()
+            .register_type::<HealCommand>()
+            .register_type::<KillCommand>()
             .register_type::<AddConditionCommand>()
-            .register_type::<RemoveConditionCommand>();
+            .register_type::<RemoveConditionCommand>()
+            .register_type::<ExpireConditionsCommand>()
+            .register_type::<RollModifierCommand>()
+            .register_type::<ResetModifierSourceCommand>()
+            .register_type::<AddModifierCardCommand>()
+            .register_type::<AddBonusCommand>()
+            .register_type::<ExpireBonusesCommand>()
+            .register_type::<InfuseElementCommand>()
+            .register_type::<WaneElementsCommand>()
+            .register_type::<MonsterAbility>()
+            .register_type::<MonsterActionCommand>()
+            .register_type::<EndTurnCommand>()
+            .register_type::<TiebreakCommand>()
+            .register_type::<InputRequest>()
+            .register_type::<InputResponse>();
+
+        app.add_event::<InputResponse>();
 
         app.add_systems(Update, step_commands);
     }
 }
 
-#[derive(Default, Resource, Reflect)]
+/* Commands of the same transaction are undone and redone together */
+/* Follow-up commands always belong to the transaction of the command that queued them up */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect)]
+pub struct Transaction(usize);
+
+#[derive(Debug, Clone, Reflect)]
+struct QueuedCommand {
+    command: ScenarioCommand,
+    transaction: Transaction,
+}
+
+#[derive(Debug, Clone, Reflect)]
+struct HistoryEntry {
+    command: ScenarioCommand,
+    transaction: Transaction,
+    /* Number of commands that were queued up by executing this command */
+    follow_ups: usize,
+}
+
+#[derive(Default, Clone, Resource, Reflect)]
 #[reflect(Resource)]
 pub struct ScenarioCommandQueue {
-    history: Vec<ScenarioCommand>,
-    pending: VecDeque<ScenarioCommand>,
+    history: Vec<HistoryEntry>,
+    pending: VecDeque<QueuedCommand>,
+    /* Undone commands as they were executed, most recent last */
+    redo: Vec<QueuedCommand>,
+    next_transaction: usize,
+    /* The command in front of pending is waiting for this to be answered */
+    input_request: Option<InputRequest>,
 }
 
 impl ScenarioCommandQueue {
+    /// Undo the last executed command and put it back in front of the pending commands
     pub fn undo(&mut self, world: &mut World) {
-        if let Some(command) = self.history.pop() {
-            self.pending.clear();
+        /* Whatever is waiting for input will ask again */
+        self.input_request = None;
+
+        if let Some(HistoryEntry {
+            command,
+            transaction,
+            follow_ups,
+        }) = self.history.pop()
+        {
+            /* Everything executed after this command is already undone, so its follow-ups are back in front */
+            /* They are queued up again once this command is executed */
+            let follow_ups = follow_ups.min(self.pending.len());
+            self.pending.drain(..follow_ups);
+            self.redo.push(QueuedCommand {
+                command: command.clone(),
+                transaction,
+            });
 
             let command = command.undo(world);
-            self.pending.push_back(command);
+            self.pending.push_front(QueuedCommand {
+                command,
+                transaction,
+            });
+        }
+    }
+
+    /// Undo all executed commands of the last transaction
+    pub fn undo_transaction(&mut self, world: &mut World) {
+        if let Some(transaction) = self.history.last().map(|entry| entry.transaction) {
+            while self
+                .history
+                .last()
+                .is_some_and(|entry| entry.transaction == transaction)
+            {
+                self.undo(world);
+            }
+        }
+    }
+
+    /// Re-run the last undone command, which queues up its follow-ups again
+    pub fn redo(&mut self, world: &mut World) {
+        if self.input_request.is_some() {
+            return;
+        }
+
+        if let Some(command) = self.redo.pop() {
+            /* The undone version of this command is waiting in front */
+            self.pending.pop_front();
+            self.pending.push_front(command);
+
+            self.execute_next(world);
+        }
+    }
+
+    /// Redo all undone commands of the last undone transaction
+    pub fn redo_transaction(&mut self, world: &mut World) {
+        if let Some(transaction) = self.redo.last().map(|queued| queued.transaction) {
+            while self
+                .redo
+                .last()
+                .is_some_and(|queued| queued.transaction == transaction)
+            {
+                let len = self.history.len();
+                self.redo(world);
+
+                /* Stop if the command did not finish */
+                if self.history.len() == len {
+                    break;
+                }
+            }
         }
     }
 
+    /// Execute pending commands until none are left or one is waiting for input
+    pub fn execute_all(&mut self, world: &mut World) {
+        while !self.pending.is_empty() && self.input_request.is_none() {
+            self.execute(world);
+        }
+    }
+
+    /// Execute the next pending command, which makes anything undone so far unavailable for redo
     pub fn execute(&mut self, world: &mut World) {
-        if let Some(mut command) = self.pending.pop_front() {
+        if self.input_request.is_some() {
+            return;
+        }
+
+        self.redo.clear();
+
+        self.execute_next(world);
+    }
+
+    fn execute_next(&mut self, world: &mut World) {
+        if let Some(QueuedCommand {
+            mut command,
+            transaction,
+        }) = self.pending.pop_front()
+        {
             match command.execute(world) {
-                ScenarionCommandExecuteResult::Pending => (),
+                ScenarionCommandExecuteResult::Pending(input_request) => {
+                    self.pending.push_front(QueuedCommand {
+                        command,
+                        transaction,
+                    });
+                    self.input_request = Some(input_request);
+                }
                 ScenarionCommandExecuteResult::Done(commands) => {
-                    let mut commands: VecDeque<_> = commands.into();
-                    self.history.push(command);
+                    self.history.push(HistoryEntry {
+                        command,
+                        transaction,
+                        follow_ups: commands.len(),
+                    });
 
-                    commands.append(&mut self.pending);
-                    self.pending = commands;
+                    for command in commands.into_iter().rev() {
+                        self.pending.push_front(QueuedCommand {
+                            command,
+                            transaction,
+                        });
+                    }
                 }
             }
         }
     }
 
+    /// Nothing left to execute and nothing waiting for input
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.input_request.is_none()
+    }
+
+    /// The request of the command that is waiting for player input
+    pub fn input_request(&self) -> Option<&InputRequest> {
+        self.input_request.as_ref()
+    }
+
+    /// Record the player's answer in the waiting command and resume with it
+    pub fn respond(&mut self, world: &mut World, choice: usize) {
+        if self.input_request.take().is_some() {
+            if let Some(queued) = self.pending.front_mut() {
+                queued.command.input(choice);
+            }
+
+            self.execute_next(world);
+        }
+    }
+
+    /// Queue up commands, each as its own transaction
     pub fn queue(&mut self, commands: Vec<ScenarioCommand>) {
-        self.pending.extend(commands);
+        for command in commands {
+            self.queue_transaction(vec![command]);
+        }
+    }
+
+    /// Queue up commands that are undone and redone together, e.g. a multi-hex move or a whole turn
+    pub fn queue_transaction(&mut self, commands: Vec<ScenarioCommand>) {
+        let transaction = Transaction(self.next_transaction);
+        self.next_transaction += 1;
+
+        self.pending
+            .extend(commands.into_iter().map(|command| QueuedCommand {
+                command,
+                transaction,
+            }));
+    }
+
+    /// Run f with the queue taken out of the world, e.g. to drive commands without keyboard input
+    /// Commands can not see the queue while it is taken out
+    pub fn scope<R>(world: &mut World, f: impl FnOnce(&mut Self, &mut World) -> R) -> R {
+        world.resource_scope(|world, mut command_queue: Mut<Self>| {
+            /* The queue is only marked as changed if f changed it, e.g. the input UI is rebuilt on changes */
+            let revision = command_queue.revision();
+            let result = f(command_queue.bypass_change_detection(), world);
+            if command_queue.revision() != revision {
+                command_queue.set_changed();
+            }
+
+            result
+        })
+    }
+
+    /* Anything done to the queue changes at least one of these */
+    fn revision(&self) -> (usize, usize, usize, usize, bool) {
+        (
+            self.history.len(),
+            self.pending.len(),
+            self.redo.len(),
+            self.next_transaction,
+            self.input_request.is_some(),
+        )
     }
 
     /// History recent to oldest
     pub fn history(&self) -> impl Iterator<Item = &ScenarioCommand> {
-        self.history.iter().rev()
+        self.history.iter().rev().map(|entry| &entry.command)
+    }
+
+    /// The state of this queue after undoing the whole history, without touching the world
+    /// Applied to the initial scenario setup, the history can be stepped through with redo
+    pub fn rewound(&self) -> Self {
+        let mut queue = Self {
+            history: vec![],
+            pending: self.pending.clone(),
+            redo: self.redo.clone(),
+            next_transaction: self.next_transaction,
+            input_request: None,
+        };
+
+        for entry in self.history.iter().rev() {
+            let queued = QueuedCommand {
+                command: entry.command.clone(),
+                transaction: entry.transaction,
+            };
+
+            let follow_ups = entry.follow_ups.min(queue.pending.len());
+            queue.pending.drain(..follow_ups);
+            queue.pending.push_front(queued.clone());
+            queue.redo.push(queued);
+        }
+
+        queue
+    }
+}
+
+impl MapEntities for ScenarioCommandQueue {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        let commands = self
+            .history
+            .iter_mut()
+            .map(|entry| &mut entry.command)
+            .chain(self.pending.iter_mut().map(|queued| &mut queued.command))
+            .chain(self.redo.iter_mut().map(|queued| &mut queued.command));
+
+        for command in commands {
+            command.map_entities(entity_mapper);
+        }
     }
 }
 
 fn step_commands(world: &mut World) {
-    /* SAFETY: Need to ensure disjoint queries, more importantly commands are not allowed to modify ButtonInput<KeyCode> or CommandQueue through World */
-    unsafe {
-        let world = world.as_unsafe_world_cell();
-        let keyboard_input = world.get_resource::<ButtonInput<KeyCode>>().unwrap();
-        let mut command_queue = world.get_resource_mut::<ScenarioCommandQueue>().unwrap();
+    let input_responses: Vec<_> = world
+        .resource_mut::<Events<InputResponse>>()
+        .drain()
+        .collect();
+    let keyboard_input = world.resource::<ButtonInput<KeyCode>>();
+    let shift = keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    /* Single steps are mostly useful for debugging */
+    let single_step = keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let enter = keyboard_input.just_pressed(KeyCode::Enter);
+    let backspace = keyboard_input.just_pressed(KeyCode::Backspace);
+
+    ScenarioCommandQueue::scope(world, |command_queue, world| {
+        for input_response in input_responses {
+            debug!("Choice {}", input_response.choice);
+
+            command_queue.respond(world, input_response.choice);
+        }
 
-        if keyboard_input.just_pressed(KeyCode::Enter) {
-            println!("Enter");
+        if enter {
+            debug!("Enter");
 
-            command_queue.execute(world.world_mut());
-        } else if keyboard_input.just_pressed(KeyCode::Backspace) {
-            println!("Backspace");
+            command_queue.execute(world);
+        } else if backspace && shift {
+            debug!("Shift+Backspace");
 
-            command_queue.undo(world.world_mut());
+            if single_step {
+                command_queue.redo(world);
+            } else {
+                command_queue.redo_transaction(world);
+            }
+        } else if backspace {
+            debug!("Backspace");
+            if let Some(command) = command_queue.history().next() {
+                debug!("Undo {:?}", command);
+            }
+
+            if single_step {
+                command_queue.undo(world);
+            } else {
+                command_queue.undo_transaction(world);
+            }
         }
-    }
+    });
 }
 
 pub enum ScenarionCommandExecuteResult {
-    Pending,
+    /* The command is executed again once the request is answered */
+    Pending(InputRequest),
     Done(Vec<ScenarioCommand>),
 }
 
@@ -107,6 +400,12 @@ pub trait ScenarioCommandTrait {
     fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult;
 
     fn undo(self, world: &mut World) -> ScenarioCommand;
+
+    /* Record the answer to the InputRequest this command is Pending on */
+    fn input(&mut self, _choice: usize) {}
+
+    /* Entities are not stable across save files and replays */
+    fn map_entities(&mut self, entity_mapper: &mut dyn EntityMapper);
 }
 
 #[allow(clippy::enum_variant_names)]
@@ -114,10 +413,171 @@ pub trait ScenarioCommandTrait {
 #[derive(Debug, Clone, Reflect)]
 pub enum ScenarioCommand {
     MoveCommand,
+    ForcedMovementCommand,
+    SpringTrapCommand,
     AttackCommand,
     ApplyAttackCommand,
     SufferDamageCommand,
+    KillCommand,
+    RetaliateCommand,
+    HealCommand,
     AddConditionCommand,
     RemoveConditionCommand,
+    ExpireConditionsCommand,
+    AddBonusCommand,
+    ExpireBonusesCommand,
     RollModifierCommand,
+    ResetModifierSourceCommand,
+    AddModifierCardCommand,
+    InfuseElementCommand,
+    WaneElementsCommand,
+    MonsterActionCommand,
+    EndTurnCommand,
+    TiebreakCommand,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::figure::{
+        condition::{ConditionKind, Conditions},
+        health::{HealCommand, Health},
+    };
+
+    use super::*;
+
+    /* A wounded and poisoned figure, healing it queues up a RemoveConditionCommand for each */
+    fn wounded_figure(world: &mut World) -> Entity {
+        let mut conditions = Conditions::new(&[]);
+        conditions.add_condition(ConditionKind::Wound);
+        conditions.add_condition(ConditionKind::Poison);
+
+        world.spawn((Health::new(10), conditions)).id()
+    }
+
+    fn has(world: &World, entity: Entity, condition: ConditionKind) -> bool {
+        world.get::<Conditions>(entity).unwrap().has(condition)
+    }
+
+    #[test]
+    fn undo_and_redo_round_trip() {
+        let mut world = World::new();
+        let entity = wounded_figure(&mut world);
+        let mut command_queue = ScenarioCommandQueue::default();
+
+        command_queue.queue(vec![HealCommand::new(entity, entity, 2).into()]);
+        command_queue.execute_all(&mut world);
+        assert_eq!(command_queue.history().count(), 3);
+        assert!(!has(&world, entity, ConditionKind::Wound));
+        assert!(!has(&world, entity, ConditionKind::Poison));
+
+        /* Undoing the follow-ups first and then the heal leaves only the heal pending */
+        for _ in 0..3 {
+            command_queue.undo(&mut world);
+        }
+        assert_eq!(command_queue.history().count(), 0);
+        assert_eq!(command_queue.pending.len(), 1);
+        assert!(has(&world, entity, ConditionKind::Wound));
+        assert!(has(&world, entity, ConditionKind::Poison));
+
+        for _ in 0..3 {
+            command_queue.redo(&mut world);
+        }
+        assert_eq!(command_queue.history().count(), 3);
+        assert!(command_queue.is_idle());
+        assert!(!has(&world, entity, ConditionKind::Wound));
+        assert!(!has(&world, entity, ConditionKind::Poison));
+    }
+
+    #[test]
+    fn executing_clears_redo() {
+        let mut world = World::new();
+        let entity = wounded_figure(&mut world);
+        let mut command_queue = ScenarioCommandQueue::default();
+
+        command_queue.queue(vec![
+            AddConditionCommand::new(entity, ConditionKind::Muddle).into(),
+            AddConditionCommand::new(entity, ConditionKind::Stun).into(),
+        ]);
+        command_queue.execute(&mut world);
+        command_queue.undo(&mut world);
+        assert_eq!(command_queue.redo.len(), 1);
+
+        command_queue.execute(&mut world);
+        assert!(command_queue.redo.is_empty());
+        assert!(has(&world, entity, ConditionKind::Muddle));
+    }
+
+    #[test]
+    fn undo_survives_missing_follow_ups() {
+        let mut world = World::new();
+        let entity = wounded_figure(&mut world);
+        let mut command_queue = ScenarioCommandQueue::default();
+
+        command_queue.queue(vec![HealCommand::new(entity, entity, 2).into()]);
+        command_queue.execute(&mut world);
+        command_queue.pending.clear();
+
+        command_queue.undo(&mut world);
+        assert_eq!(command_queue.pending.len(), 1);
+    }
+
+    #[test]
+    fn transactions_are_undone_and_redone_together() {
+        let mut world = World::new();
+        let entity = wounded_figure(&mut world);
+        let mut command_queue = ScenarioCommandQueue::default();
+
+        command_queue.queue(vec![AddConditionCommand::new(
+            entity,
+            ConditionKind::Muddle,
+        )
+        .into()]);
+        command_queue.queue_transaction(vec![
+            AddConditionCommand::new(entity, ConditionKind::Stun).into(),
+            HealCommand::new(entity, entity, 2).into(),
+        ]);
+        command_queue.execute_all(&mut world);
+        assert_eq!(command_queue.history().count(), 5);
+
+        /* The heal, its follow-ups and the stun belong to the second transaction */
+        command_queue.undo_transaction(&mut world);
+        assert_eq!(command_queue.history().count(), 1);
+        assert!(has(&world, entity, ConditionKind::Muddle));
+        assert!(!has(&world, entity, ConditionKind::Stun));
+        assert!(has(&world, entity, ConditionKind::Wound));
+
+        command_queue.undo_transaction(&mut world);
+        assert_eq!(command_queue.history().count(), 0);
+        assert!(!has(&world, entity, ConditionKind::Muddle));
+
+        command_queue.redo_transaction(&mut world);
+        assert_eq!(command_queue.history().count(), 1);
+        assert!(has(&world, entity, ConditionKind::Muddle));
+        assert!(!has(&world, entity, ConditionKind::Stun));
+
+        command_queue.redo_transaction(&mut world);
+        assert_eq!(command_queue.history().count(), 5);
+        assert!(has(&world, entity, ConditionKind::Stun));
+        assert!(!has(&world, entity, ConditionKind::Wound));
+        assert!(command_queue.is_idle());
+    }
+
+    #[test]
+    fn scope_only_marks_the_queue_changed_if_it_changed() {
+        let mut world = World::new();
+        let entity = wounded_figure(&mut world);
+        world.init_resource::<ScenarioCommandQueue>();
+        world.clear_trackers();
+
+        ScenarioCommandQueue::scope(&mut world, |command_queue, world| {
+            command_queue.execute_all(world);
+        });
+        assert!(!world.is_resource_changed::<ScenarioCommandQueue>());
+
+        ScenarioCommandQueue::scope(&mut world, |command_queue, world| {
+            command_queue.queue(vec![HealCommand::new(entity, entity, 2).into()]);
+            command_queue.execute_all(world);
+        });
+        assert!(world.is_resource_changed::<ScenarioCommandQueue>());
+    }
 }
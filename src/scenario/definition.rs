@@ -0,0 +1,275 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+    scene::ron,
+    utils::HashMap,
+};
+use hexx::{Hex, HexLayout};
+use serde::Deserialize;
+
+use crate::figure::{
+    bonus::Retaliate,
+    condition::ConditionKind,
+    modifier::{deck::ModifierDeck, ModifierSourceOwner, ModifierTray},
+    FigureId, FigureInstance, MonsterRank, Team,
+};
+
+use super::{
+    command::ScenarioCommandQueue,
+    setup::{despawn_scenario, FigureSetup, OverlaySetup, Ruleset, ScenarioGoal, ScenarioSetup},
+};
+
+/* Scenarios are authored as RON assets, e.g. assets/scenarios/demo.scenario.ron */
+pub struct ScenarioDefinitionPlugin;
+
+impl Plugin for ScenarioDefinitionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ScenarioDefinition>()
+            .init_asset_loader::<ScenarioDefinitionLoader>()
+            .add_event::<ScenarioSpawned>();
+
+        app.add_systems(Update, spawn_scenario_on_load);
+    }
+}
+
+#[derive(Debug, Clone, Asset, TypePath, Deserialize)]
+pub struct ScenarioDefinition {
+    pub hexes: Vec<Hex>,
+    #[serde(default)]
+    pub walls: Vec<Hex>,
+    #[serde(default)]
+    pub overlays: Vec<OverlaySetup>,
+    /* Characters are placed on these in order */
+    pub starting_positions: Vec<Hex>,
+    pub monsters: Vec<MonsterSpawn>,
+    #[serde(default)]
+    pub ruleset: Ruleset,
+    /* Only the ones of the ruleset are used */
+    #[serde(default)]
+    pub modifier_trays: Vec<ModifierTray>,
+    #[serde(default)]
+    pub modifier_decks: Vec<ModifierDeck>,
+    pub goals: Vec<ScenarioGoal>,
+    #[serde(default)]
+    pub special_rules: Vec<String>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MonsterSpawn {
+    pub id: FigureId,
+    pub rank: MonsterRank,
+    pub hex: Hex,
+    pub health: usize,
+    #[serde(default)]
+    pub shield: usize,
+    #[serde(default)]
+    pub retaliate: Option<Retaliate>,
+    #[serde(default)]
+    pub immunities: Vec<ConditionKind>,
+}
+
+/* Characters are not part of a scenario, they are brought along by the players */
+#[derive(Debug, Clone)]
+pub struct CharacterSetup {
+    pub id: FigureId,
+    pub health: usize,
+    pub modifier_tray: ModifierTray,
+    pub modifier_deck: ModifierDeck,
+}
+
+impl ScenarioDefinition {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ScenarioDefinitionError> {
+        Ok(ron::de::from_bytes(bytes)?)
+    }
+
+    /// Combine this with the characters of the players into something to spawn
+    /// Characters without a starting position are left out
+    pub fn setup(&self, layout: HexLayout, characters: Vec<CharacterSetup>) -> ScenarioSetup {
+        let mut instances: HashMap<FigureId, u32> = HashMap::new();
+        let monsters = self.monsters.iter().map(|monster| {
+            let instance = instances.entry(monster.id).or_default();
+            let figure = FigureSetup {
+                id: monster.id,
+                instance: FigureInstance::new(*instance),
+                team: Team::Monster,
+                hex: monster.hex,
+                health: monster.health,
+                shield: monster.shield,
+                retaliate: monster.retaliate,
+                immunities: monster.immunities.clone(),
+                rank: Some(monster.rank),
+                summon: false,
+            };
+            *instance += 1;
+
+            figure
+        });
+
+        let character_figures =
+            characters
+                .iter()
+                .zip(&self.starting_positions)
+                .map(|(character, hex)| FigureSetup {
+                    id: character.id,
+                    instance: FigureInstance::new(0),
+                    team: Team::Player,
+                    hex: *hex,
+                    health: character.health,
+                    shield: 0,
+                    retaliate: None,
+                    immunities: vec![],
+                    rank: None,
+                    summon: false,
+                });
+
+        let (modifier_trays, modifier_decks) = match self.ruleset {
+            Ruleset::ButtonsAndBugs => (
+                characters
+                    .iter()
+                    .map(|character| character.modifier_tray.clone())
+                    .chain(self.modifier_trays.iter().cloned())
+                    .collect(),
+                vec![],
+            ),
+            Ruleset::Gloomhaven => {
+                let mut modifier_decks: Vec<ModifierDeck> = characters
+                    .iter()
+                    .map(|character| character.modifier_deck.clone())
+                    .chain(self.modifier_decks.iter().cloned())
+                    .collect();
+                /* Monsters without a deck of their own share the standard one */
+                if !modifier_decks
+                    .iter()
+                    .any(|modifier_deck| modifier_deck.owner() == ModifierSourceOwner::Monsters)
+                {
+                    modifier_decks.push(ModifierDeck::standard(ModifierSourceOwner::Monsters));
+                }
+
+                (vec![], modifier_decks)
+            }
+        };
+
+        ScenarioSetup {
+            layout,
+            hexes: self.hexes.clone(),
+            walls: self.walls.clone(),
+            overlays: self.overlays.clone(),
+            figures: character_figures.chain(monsters).collect(),
+            modifier_trays,
+            modifier_decks,
+            goals: self.goals.clone(),
+            special_rules: self.special_rules.clone(),
+            seed: self.seed,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ScenarioDefinitionLoader;
+
+impl AssetLoader for ScenarioDefinitionLoader {
+    type Asset = ScenarioDefinition;
+    type Settings = ();
+    type Error = ScenarioDefinitionError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        ScenarioDefinition::from_bytes(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["scenario.ron"]
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioDefinitionError {
+    Io(std::io::Error),
+    Ron(ron::de::SpannedError),
+}
+
+impl std::fmt::Display for ScenarioDefinitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScenarioDefinitionError::Io(error) => write!(f, "{}", error),
+            ScenarioDefinitionError::Ron(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioDefinitionError {}
+
+impl From<std::io::Error> for ScenarioDefinitionError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<ron::de::SpannedError> for ScenarioDefinitionError {
+    fn from(error: ron::de::SpannedError) -> Self {
+        Self::Ron(error)
+    }
+}
+
+/* Insert this to spawn a scenario as soon as its definition is loaded */
+/* It is spawned again whenever the definition changes on disk */
+#[derive(Debug, Resource)]
+pub struct ScenarioToSpawn {
+    pub definition: Handle<ScenarioDefinition>,
+    pub layout: HexLayout,
+    pub characters: Vec<CharacterSetup>,
+}
+
+/* This is fired whenever a scenario was spawned from its definition */
+#[derive(Debug, Event)]
+pub struct ScenarioSpawned {
+    pub figures: HashMap<(FigureId, FigureInstance), Entity>,
+}
+
+pub fn spawn_scenario_on_load(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<ScenarioDefinition>>,
+    scenario_definitions: Res<Assets<ScenarioDefinition>>,
+    scenario_to_spawn: Option<Res<ScenarioToSpawn>>,
+    mut scenario_spawned: EventWriter<ScenarioSpawned>,
+) {
+    let Some(scenario_to_spawn) = scenario_to_spawn else {
+        return;
+    };
+
+    for asset_event in asset_events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = asset_event
+        else {
+            continue;
+        };
+        if *id != scenario_to_spawn.definition.id() {
+            continue;
+        }
+        let Some(scenario_definition) = scenario_definitions.get(*id) else {
+            continue;
+        };
+
+        let scenario_setup = scenario_definition.setup(
+            scenario_to_spawn.layout.clone(),
+            scenario_to_spawn.characters.clone(),
+        );
+
+        /* Whatever was queued up belongs to the previous scenario */
+        commands.queue(despawn_scenario);
+        commands.insert_resource(ScenarioCommandQueue::default());
+
+        let figures = scenario_setup.spawn(&mut commands);
+        commands.insert_resource(scenario_setup);
+
+        scenario_spawned.send(ScenarioSpawned { figures });
+    }
+}
@@ -0,0 +1,170 @@
+use bevy::{ecs::entity::EntityMapper, prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use super::command::{
+    ScenarioCommand, ScenarioCommandQueue, ScenarioCommandTrait, ScenarionCommandExecuteResult,
+};
+
+/*
+    Elements are shared by all figures of the scenario:
+    - infusing an element makes it strong
+    - at the end of each round strong elements wane and waning ones become inert again
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect, Deserialize)]
+pub enum Element {
+    Fire,
+    Ice,
+    Air,
+    Earth,
+    Light,
+    Dark,
+    Wild,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum ElementStrength {
+    #[default]
+    Inert,
+    Waning,
+    Strong,
+}
+
+impl ElementStrength {
+    pub fn waned(self) -> Self {
+        match self {
+            ElementStrength::Strong => ElementStrength::Waning,
+            ElementStrength::Waning | ElementStrength::Inert => ElementStrength::Inert,
+        }
+    }
+}
+
+/* Elements missing here are inert */
+#[derive(Debug, Default, Clone, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Elements(HashMap<Element, ElementStrength>);
+
+impl Elements {
+    pub fn get(&self, element: Element) -> ElementStrength {
+        self.0.get(&element).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, element: Element, strength: ElementStrength) {
+        self.0.insert(element, strength);
+    }
+
+    pub fn wane(&mut self) {
+        self.0.retain(|_, strength| {
+            *strength = strength.waned();
+            *strength != ElementStrength::Inert
+        });
+    }
+}
+
+pub fn wane_elements(mut command_queue: ResMut<ScenarioCommandQueue>, elements: Res<Elements>) {
+    if !elements.0.is_empty() {
+        command_queue.queue(vec![WaneElementsCommand::default().into()]);
+    }
+}
+
+#[derive(Debug, Clone, Reflect)]
+pub struct InfuseElementCommand {
+    element: Element,
+    previous: Option<ElementStrength>,
+}
+
+impl InfuseElementCommand {
+    pub fn new(element: Element) -> Self {
+        Self {
+            element,
+            previous: Default::default(),
+        }
+    }
+}
+
+impl ScenarioCommandTrait for InfuseElementCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let mut elements = world.resource_mut::<Elements>();
+
+        self.previous = Some(elements.get(self.element));
+        elements.set(self.element, ElementStrength::Strong);
+        debug!("Infuse {:?}", self.element);
+
+        ScenarionCommandExecuteResult::Done(vec![])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        let mut elements = world.resource_mut::<Elements>();
+
+        elements.set(self.element, self.previous.unwrap());
+
+        let command = Self {
+            previous: None,
+            ..self
+        };
+        command.into()
+    }
+
+    fn map_entities(&mut self, _entity_mapper: &mut dyn EntityMapper) {}
+}
+
+#[derive(Debug, Default, Clone, Reflect)]
+pub struct WaneElementsCommand {
+    previous: Option<Elements>,
+}
+
+impl ScenarioCommandTrait for WaneElementsCommand {
+    fn execute(&mut self, world: &mut World) -> ScenarionCommandExecuteResult {
+        let mut elements = world.resource_mut::<Elements>();
+
+        self.previous = Some(elements.clone());
+        elements.wane();
+
+        ScenarionCommandExecuteResult::Done(vec![])
+    }
+
+    fn undo(self, world: &mut World) -> ScenarioCommand {
+        world.insert_resource(self.previous.unwrap());
+
+        let command = Self { previous: None };
+        command.into()
+    }
+
+    fn map_entities(&mut self, _entity_mapper: &mut dyn EntityMapper) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infused_elements_wane_over_two_rounds() {
+        let mut world = World::new();
+        world.init_resource::<Elements>();
+
+        InfuseElementCommand::new(Element::Fire).execute(&mut world);
+        assert_eq!(
+            world.resource::<Elements>().get(Element::Fire),
+            ElementStrength::Strong
+        );
+
+        WaneElementsCommand::default().execute(&mut world);
+        assert_eq!(
+            world.resource::<Elements>().get(Element::Fire),
+            ElementStrength::Waning
+        );
+
+        let mut command = WaneElementsCommand::default();
+        command.execute(&mut world);
+        assert_eq!(
+            world.resource::<Elements>().get(Element::Fire),
+            ElementStrength::Inert
+        );
+
+        command.undo(&mut world);
+        assert_eq!(
+            world.resource::<Elements>().get(Element::Fire),
+            ElementStrength::Waning
+        );
+    }
+}
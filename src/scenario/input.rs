@@ -0,0 +1,137 @@
+use bevy::{color::palettes::css::DARK_SLATE_GRAY, prelude::*};
+
+use super::command::ScenarioCommandQueue;
+
+/* This shows whatever the command queue is waiting for and lets the player pick an option */
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (show_input_request, select_input_option));
+    }
+}
+
+/* A command that needs a decision of the player returns this as Pending */
+/* The decision is handed back to the command as index into options */
+#[derive(Debug, Clone, Reflect)]
+pub struct InputRequest {
+    prompt: String,
+    options: Vec<String>,
+}
+
+impl InputRequest {
+    pub fn new(prompt: impl Into<String>, options: Vec<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            options,
+        }
+    }
+
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+
+    pub fn options(&self) -> &[String] {
+        &self.options
+    }
+}
+
+/* This is fired whenever the player answers the current InputRequest */
+#[derive(Debug, Event, Reflect)]
+pub struct InputResponse {
+    pub choice: usize,
+}
+
+#[derive(Debug, Component, Reflect)]
+struct InputRequestUi;
+
+#[derive(Debug, Component, Reflect)]
+struct InputOption(usize);
+
+fn show_input_request(
+    mut commands: Commands,
+    command_queue: Res<ScenarioCommandQueue>,
+    input_request_uis: Query<Entity, With<InputRequestUi>>,
+) {
+    if !command_queue.is_changed() {
+        return;
+    }
+
+    for entity in &input_request_uis {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    if let Some(input_request) = command_queue.input_request() {
+        commands
+            .spawn((
+                InputRequestUi,
+                Node {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(5.0),
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..default()
+                },
+                BackgroundColor(Color::from(DARK_SLATE_GRAY)),
+            ))
+            .with_children(|b| {
+                b.spawn(Text::new(input_request.prompt()));
+
+                for (index, option) in input_request.options().iter().enumerate() {
+                    b.spawn((
+                        Button,
+                        InputOption(index),
+                        Node {
+                            padding: UiRect::all(Val::Px(5.0)),
+                            ..default()
+                        },
+                        BackgroundColor(Color::BLACK),
+                    ))
+                    .with_children(|b| {
+                        b.spawn(Text::new(format!("{}: {}", index + 1, option)));
+                    });
+                }
+            });
+    }
+}
+
+const OPTION_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+fn select_input_option(
+    input_options: Query<(&Interaction, &InputOption), Changed<Interaction>>,
+    all_input_options: Query<&InputOption>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut input_responses: EventWriter<InputResponse>,
+) {
+    for (interaction, input_option) in &input_options {
+        if *interaction == Interaction::Pressed {
+            input_responses.send(InputResponse {
+                choice: input_option.0,
+            });
+        }
+    }
+
+    /* Options can also be picked by their number */
+    for input_option in &all_input_options {
+        if OPTION_KEYS
+            .get(input_option.0)
+            .is_some_and(|key| keyboard_input.just_pressed(*key))
+        {
+            input_responses.send(InputResponse {
+                choice: input_option.0,
+            });
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use bevy::{math::Vec2, utils::HashSet};
+use hexx::{Hex, HexLayout};
+
+/*
+    Line of sight follows the corner to corner rule:
+    It exists if a line can be drawn from any corner of one hex to any corner of the other
+    without touching any part of a wall hex. Obstacles and figures do not block it.
+*/
+
+/* Lines passing this close to a wall count as touching it */
+const TOUCH_DISTANCE: f32 = 1e-4;
+
+pub fn line_of_sight(walls: &HashSet<Hex>, from: Hex, to: Hex) -> bool {
+    if walls.is_empty() || from == to {
+        return true;
+    }
+
+    /* The result is the same for any orientation and size, so a unit layout is used */
+    let layout = HexLayout::default();
+    let wall_edges: Vec<[Vec2; 2]> = walls
+        .iter()
+        .flat_map(|wall| layout.hex_edge_corners(*wall))
+        .collect();
+
+    layout.hex_corners(from).iter().any(|from_corner| {
+        layout.hex_corners(to).iter().any(|to_corner| {
+            wall_edges.iter().all(|[start, end]| {
+                segment_distance(*from_corner, *to_corner, *start, *end) > TOUCH_DISTANCE
+            })
+        })
+    })
+}
+
+/* 1 or -1 for the side of the line through start and end the point is on, 0 if it is on the line */
+fn side(start: Vec2, end: Vec2, point: Vec2) -> i8 {
+    let cross = (end - start).perp_dot(point - start);
+    if cross.abs() <= TOUCH_DISTANCE {
+        0
+    } else if cross > 0.0 {
+        1
+    } else {
+        -1
+    }
+}
+
+fn segment_distance(a1: Vec2, a2: Vec2, b1: Vec2, b2: Vec2) -> f32 {
+    /* Touching and collinear segments are left to the distances between them */
+    let crosses = |p1: Vec2, p2: Vec2, q1: Vec2, q2: Vec2| side(p1, p2, q1) * side(p1, p2, q2) < 0;
+    if crosses(a1, a2, b1, b2) && crosses(b1, b2, a1, a2) {
+        return 0.0;
+    }
+
+    [
+        point_segment_distance(a1, b1, b2),
+        point_segment_distance(a2, b1, b2),
+        point_segment_distance(b1, a1, a2),
+        point_segment_distance(b2, a1, a2),
+    ]
+    .into_iter()
+    .fold(f32::INFINITY, f32::min)
+}
+
+fn point_segment_distance(point: Vec2, start: Vec2, end: Vec2) -> f32 {
+    let segment = end - start;
+    let t = ((point - start).dot(segment) / segment.length_squared()).clamp(0.0, 1.0);
+
+    point.distance(start + segment * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walls(hexes: &[Hex]) -> HashSet<Hex> {
+        hexes.iter().copied().collect()
+    }
+
+    #[test]
+    fn walls_block_lines_through_them() {
+        let walls = walls(&[Hex::new(1, 0)]);
+
+        assert!(!line_of_sight(&walls, Hex::ZERO, Hex::new(1, 0)));
+        assert!(!line_of_sight(&walls, Hex::ZERO, Hex::new(3, 0)));
+    }
+
+    #[test]
+    fn lines_grazing_a_wall_corner_are_blocked() {
+        /* The best lines of (0,0) to (2,0) only touch the corners of (1,0) */
+        let walls = walls(&[Hex::new(1, 0)]);
+
+        assert!(!line_of_sight(&walls, Hex::ZERO, Hex::new(2, 0)));
+        assert!(line_of_sight(&HashSet::new(), Hex::ZERO, Hex::new(2, 0)));
+    }
+
+    #[test]
+    fn lines_passing_a_wall_corner_are_clear() {
+        let walls = walls(&[Hex::new(1, 0)]);
+
+        assert!(line_of_sight(&walls, Hex::ZERO, Hex::new(2, -1)));
+        assert!(line_of_sight(&walls, Hex::ZERO, Hex::new(3, -1)));
+        assert!(line_of_sight(&walls, Hex::ZERO, Hex::new(1, 1)));
+    }
+
+    #[test]
+    fn neighbors_see_each_other_between_walls() {
+        /* Both hexes next to the shared edge of (0,0) and (1,0) are walls */
+        let walls = walls(&[Hex::new(1, -1), Hex::new(0, 1)]);
+
+        assert!(line_of_sight(&walls, Hex::ZERO, Hex::new(1, 0)));
+    }
+
+    #[test]
+    fn collinear_segments_only_touch_if_they_overlap() {
+        /* Rounding puts the collinear points slightly off the line on either side */
+        let a1 = Vec2::new(0.0, 0.0);
+        let a2 = Vec2::new(1.0, 1e-7);
+        let b1 = Vec2::new(2.0, -1e-7);
+        let b2 = Vec2::new(3.0, 1e-7);
+
+        assert!(segment_distance(a1, a2, b1, b2) > 0.9);
+        assert!(segment_distance(a1, a2, Vec2::new(0.5, 0.0), b2) < TOUCH_DISTANCE);
+    }
+
+    #[test]
+    fn crossing_and_touching_segments_have_no_distance() {
+        let a1 = Vec2::new(0.0, 0.0);
+        let a2 = Vec2::new(2.0, 0.0);
+
+        assert_eq!(
+            segment_distance(a1, a2, Vec2::new(1.0, -1.0), Vec2::new(1.0, 1.0)),
+            0.0
+        );
+        assert!(
+            segment_distance(a1, a2, Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0)) < TOUCH_DISTANCE
+        );
+        assert_eq!(
+            segment_distance(a1, a2, Vec2::new(1.0, 0.5), Vec2::new(1.0, 1.0)),
+            0.5
+        );
+    }
+}
@@ -1,9 +1,12 @@
 use bevy::{
     ecs::{component::ComponentId, world::DeferredWorld},
     prelude::*,
-    utils::HashMap,
+    utils::{HashMap, HashSet},
 };
 use hexx::{Hex, HexLayout};
+use serde::Deserialize;
+
+use super::line_of_sight::line_of_sight;
 
 /* This resource is used to retrieve the active map entity for hierarchy reasons of overlay tiles spawning */
 /* Can also be used to despawn the whole map entity */
@@ -23,6 +26,8 @@ pub struct HexGrid {
     ground_entities: HashMap<Hex, Entity>,
     overlay_entities: HashMap<Hex, Entity>,
     figure_entities: HashMap<Hex, Entity>,
+    /* Walls are not tiles, nothing can enter them and they block line of sight */
+    walls: HashSet<Hex>,
 }
 
 impl HexGrid {
@@ -32,9 +37,14 @@ impl HexGrid {
             ground_entities: HashMap::new(),
             overlay_entities: HashMap::new(),
             figure_entities: HashMap::new(),
+            walls: HashSet::new(),
         }
     }
 
+    pub fn layout(&self) -> &HexLayout {
+        &self.layout
+    }
+
     pub fn remove(&mut self, hex: &Hex, layer: &HexLayer) {
         let map = self.get_layer_map_mut(layer);
 
@@ -47,7 +57,29 @@ impl HexGrid {
         map.insert(hex, entity);
     }
 
-    fn _get_layer_map(&self, layer: &HexLayer) -> &HashMap<Hex, Entity> {
+    pub fn insert_wall(&mut self, hex: Hex) {
+        self.walls.insert(hex);
+    }
+
+    pub fn walls(&self) -> &HashSet<Hex> {
+        &self.walls
+    }
+
+    pub fn line_of_sight(&self, from: Hex, to: Hex) -> bool {
+        line_of_sight(&self.walls, from, to)
+    }
+
+    pub fn get(&self, hex: &Hex, layer: &HexLayer) -> Option<Entity> {
+        self.get_layer_map(layer).get(hex).copied()
+    }
+
+    pub fn iter(&self, layer: &HexLayer) -> impl Iterator<Item = (Hex, Entity)> + '_ {
+        self.get_layer_map(layer)
+            .iter()
+            .map(|(hex, entity)| (*hex, *entity))
+    }
+
+    fn get_layer_map(&self, layer: &HexLayer) -> &HashMap<Hex, Entity> {
         match layer {
             HexLayer::Ground => &self.ground_entities,
             HexLayer::Overlay => &self.overlay_entities,
@@ -89,6 +121,16 @@ impl HexLayer {
     }
 }
 
+/* This component is inserted on overlay tiles and decides how a hex can be moved through */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect, Deserialize)]
+#[reflect(Component)]
+pub enum OverlayKind {
+    Obstacle,
+    DifficultTerrain,
+    HazardousTerrain,
+    Trap,
+}
+
 #[derive(Debug, Component, Reflect)]
 #[reflect(Component)]
 #[component(on_remove = HexPosition::on_remove)]
@@ -116,7 +158,7 @@ impl HexPosition {
         hex_grid.remove(&self.hex, &self.layer);
 
         /* Insert at new place */
-        hex_grid.insert(self.hex, &self.layer, entity);
+        hex_grid.insert(hex, &self.layer, entity);
 
         /* Update the actual position */
         self.hex = hex;
@@ -1,18 +1,46 @@
+use crate::game::{not_restoring_save, RoundState};
 use bevy::prelude::*;
-use map::{hex_position_to_transform, ActiveMap, HexGrid, HexLayer, HexPosition};
+use element::{Element, ElementStrength, Elements};
+use map::{hex_position_to_transform, ActiveMap, HexGrid, HexLayer, HexPosition, OverlayKind};
+use rng::ScenarioRng;
+use setup::ScenarioSetup;
 
 pub mod command;
+pub mod definition;
+pub mod element;
+pub mod input;
+pub mod line_of_sight;
 pub mod map;
+/* Only tests read maps from text so far */
+#[cfg(test)]
+pub mod notation;
+pub mod pathfinding;
+pub mod replay;
+pub mod rng;
+pub mod save;
+pub mod setup;
 pub struct ScenarioPlugin;
 
 impl Plugin for ScenarioPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Update, hex_position_to_transform)
-            .init_resource::<ActiveMap>();
+            .init_resource::<ActiveMap>()
+            .init_resource::<ScenarioRng>()
+            .init_resource::<Elements>()
+            .add_systems(
+                OnEnter(RoundState::EndOfRound),
+                element::wane_elements.run_if(not_restoring_save),
+            );
 
         app.register_type::<ActiveMap>();
         app.register_type::<HexGrid>();
         app.register_type::<HexLayer>();
         app.register_type::<HexPosition>();
+        app.register_type::<OverlayKind>();
+        app.register_type::<ScenarioSetup>();
+        app.register_type::<ScenarioRng>();
+        app.register_type::<Element>();
+        app.register_type::<ElementStrength>();
+        app.register_type::<Elements>();
     }
 }
@@ -0,0 +1,369 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use hexx::{Hex, HexLayout};
+
+use crate::figure::{FigureId, FigureInstance, MonsterRank, Summon, Team};
+
+use super::{
+    map::{HexGrid, HexLayer, OverlayKind},
+    setup::{FigureSetup, OverlaySetup, ScenarioSetup},
+};
+
+/*
+    ASCII notation for hex maps, used by tests and for debugging.
+    Every line is a row of hexes, shifted by half a hex against the one above:
+
+        .   .   #   .
+          .   M1  O   P0
+        .   T   .   E2D
+
+    Cells are four characters apart:
+        .       ground
+        #       wall
+        O D H T obstacle, difficult terrain, hazardous terrain, trap on ground
+        P A M E player, ally, normal monster, elite monster followed by their FigureId
+                and optionally the overlay they stand on
+        S       summon, followed by the FigureId of the character that summoned it
+        *       ground that is part of a path, only when printing
+
+    The first cell of the first line is Hex (0, 0), rows go towards positive y.
+*/
+
+/* Figures have no health in the notation, they all get this */
+pub const FIGURE_HEALTH: usize = 10;
+const CELL_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HexMapError {
+    /* A cell that does not line up with the hexes of its row */
+    Misaligned { line: usize, column: usize },
+    UnknownCell { line: usize, cell: String },
+}
+
+impl std::fmt::Display for HexMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HexMapError::Misaligned { line, column } => {
+                write!(f, "Cell at line {} column {} is not on a hex", line, column)
+            }
+            HexMapError::UnknownCell { line, cell } => {
+                write!(f, "Unknown cell {} at line {}", cell, line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HexMapError {}
+
+/// Parse a hex map into a setup that spawns it as HexGrid with HexPosition children
+/// Figures of the same FigureId become consecutive instances in reading order
+pub fn parse_hex_map(text: &str) -> Result<ScenarioSetup, HexMapError> {
+    let mut setup = ScenarioSetup {
+        layout: HexLayout::default(),
+        hexes: vec![],
+        walls: vec![],
+        overlays: vec![],
+        figures: vec![],
+        modifier_trays: vec![],
+        modifier_decks: vec![],
+        goals: vec![],
+        special_rules: vec![],
+        seed: 0,
+    };
+    let mut instances: HashMap<FigureId, u32> = HashMap::new();
+
+    let lines: Vec<&str> = text.lines().collect();
+    let Some(first) = lines.iter().position(|line| !line.trim().is_empty()) else {
+        return Ok(setup);
+    };
+    let origin = lines[first].len() - lines[first].trim_start().len();
+
+    for (row, line) in lines[first..].iter().enumerate() {
+        let line_number = first + row + 1;
+        for (column, cell) in cells(line) {
+            /* Columns are CELL_WIDTH / 2 apart per hex step in x, half of that per row */
+            let offset = column as i32 - origin as i32 - (row * CELL_WIDTH / 2) as i32;
+            if offset % CELL_WIDTH as i32 != 0 {
+                return Err(HexMapError::Misaligned {
+                    line: line_number,
+                    column: column + 1,
+                });
+            }
+            let hex = Hex::new(offset / CELL_WIDTH as i32, row as i32);
+            let unknown_cell = || HexMapError::UnknownCell {
+                line: line_number,
+                cell: cell.to_string(),
+            };
+
+            let mut chars = cell.chars();
+            let first_char = chars.next().unwrap();
+            if first_char == '#' && cell.len() == 1 {
+                setup.walls.push(hex);
+                continue;
+            }
+            setup.hexes.push(hex);
+
+            let figure = match first_char {
+                'P' => Some((Team::Player, None, false)),
+                'A' => Some((Team::Ally, None, false)),
+                'S' => Some((Team::Ally, None, true)),
+                'M' => Some((Team::Monster, Some(MonsterRank::Normal), false)),
+                'E' => Some((Team::Monster, Some(MonsterRank::Elite), false)),
+                _ => None,
+            };
+            let overlay = if let Some((team, rank, summon)) = figure {
+                let digits: String = chars.clone().take_while(char::is_ascii_digit).collect();
+                let id = FigureId::new(digits.parse().map_err(|_| unknown_cell())?);
+                let instance = instances.entry(id).or_default();
+                setup.figures.push(FigureSetup {
+                    id,
+                    instance: FigureInstance::new(*instance),
+                    team,
+                    hex,
+                    health: FIGURE_HEALTH,
+                    shield: 0,
+                    retaliate: None,
+                    immunities: vec![],
+                    rank,
+                    summon,
+                });
+                *instance += 1;
+
+                chars.nth(digits.len())
+            } else {
+                Some(first_char).filter(|first_char| !matches!(first_char, '.' | '*'))
+            };
+
+            if let Some(overlay) = overlay {
+                let kind = overlay_kind(overlay).ok_or_else(unknown_cell)?;
+                setup.overlays.push(OverlaySetup { hex, kind });
+            }
+            if chars.next().is_some() {
+                return Err(unknown_cell());
+            }
+        }
+    }
+
+    Ok(setup)
+}
+
+/// Print the HexGrid as it currently is, marking the given path on empty ground
+pub fn print_hex_map(world: &World, hex_grid: Entity, path: &[Hex]) -> String {
+    let hex_grid = world.get::<HexGrid>(hex_grid).unwrap();
+    let path: HashSet<Hex> = path.iter().copied().collect();
+
+    let mut cells: HashMap<Hex, String> = HashMap::new();
+    for (hex, _) in hex_grid.iter(&HexLayer::Ground) {
+        let cell = if path.contains(&hex) { "*" } else { "." };
+        cells.insert(hex, cell.to_string());
+    }
+    for wall in hex_grid.walls() {
+        cells.insert(*wall, "#".to_string());
+    }
+    for (hex, entity) in hex_grid.iter(&HexLayer::Overlay) {
+        if let Some(kind) = world.get::<OverlayKind>(entity) {
+            cells.insert(hex, overlay_char(*kind).to_string());
+        }
+    }
+    for (hex, entity) in hex_grid.iter(&HexLayer::Figure) {
+        let letter = match (world.get::<Team>(entity), world.get::<MonsterRank>(entity)) {
+            _ if world.get::<Summon>(entity).is_some() => 'S',
+            (Some(Team::Player), _) => 'P',
+            (Some(Team::Ally), _) => 'A',
+            (_, Some(MonsterRank::Elite)) => 'E',
+            _ => 'M',
+        };
+        let id = world
+            .get::<FigureId>(entity)
+            .map(FigureId::id)
+            .unwrap_or_default();
+        let overlay = hex_grid
+            .get(&hex, &HexLayer::Overlay)
+            .and_then(|overlay| world.get::<OverlayKind>(overlay))
+            .map(|kind| overlay_char(*kind).to_string())
+            .unwrap_or_default();
+        cells.insert(hex, format!("{}{}{}", letter, id, overlay));
+    }
+
+    render(&cells)
+}
+
+/* Non-whitespace runs of a line with the column they start at */
+fn cells(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split_whitespace()
+        .map(move |cell| (cell.as_ptr() as usize - line.as_ptr() as usize, cell))
+}
+
+fn render(cells: &HashMap<Hex, String>) -> String {
+    let Some(min_y) = cells.keys().map(|hex| hex.y).min() else {
+        return String::new();
+    };
+    let max_y = cells.keys().map(|hex| hex.y).max().unwrap();
+    let column = |hex: &Hex| (hex.x * 2 + hex.y) * CELL_WIDTH as i32 / 2;
+    let min_column = cells.keys().map(column).min().unwrap();
+
+    let mut lines = vec![];
+    for y in min_y..=max_y {
+        let mut row: Vec<(&Hex, &String)> = cells.iter().filter(|(hex, _)| hex.y == y).collect();
+        row.sort_by_key(|(hex, _)| hex.x);
+
+        let mut line = String::new();
+        for (hex, cell) in row {
+            let start = (column(hex) - min_column) as usize;
+            line.push_str(&" ".repeat(start.saturating_sub(line.len())));
+            line.push_str(cell);
+        }
+        lines.push(line);
+    }
+
+    lines.join("\n")
+}
+
+fn overlay_kind(overlay: char) -> Option<OverlayKind> {
+    match overlay {
+        'O' => Some(OverlayKind::Obstacle),
+        'D' => Some(OverlayKind::DifficultTerrain),
+        'H' => Some(OverlayKind::HazardousTerrain),
+        'T' => Some(OverlayKind::Trap),
+        _ => None,
+    }
+}
+
+fn overlay_char(kind: OverlayKind) -> char {
+    match kind {
+        OverlayKind::Obstacle => 'O',
+        OverlayKind::DifficultTerrain => 'D',
+        OverlayKind::HazardousTerrain => 'H',
+        OverlayKind::Trap => 'T',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::figure::{
+        ai::{monster_turn, MonsterAbility},
+        movement::find_move_path,
+    };
+
+    use super::*;
+
+    const MAP: &str = "\
+.   .   #   .   .
+  .   M1  O   P0  .
+.   T   .   E2D .";
+
+    fn spawn(text: &str) -> (World, Entity, HashMap<(FigureId, FigureInstance), Entity>) {
+        let mut world = World::new();
+        let figures = parse_hex_map(text).unwrap().spawn(&mut world.commands());
+        world.flush();
+        let hex_grid = world
+            .query_filtered::<Entity, With<HexGrid>>()
+            .single(&world);
+
+        (world, hex_grid, figures)
+    }
+
+    #[test]
+    fn parses_cells_onto_hexes() {
+        let setup = parse_hex_map(MAP).unwrap();
+
+        assert_eq!(setup.walls, vec![Hex::new(2, 0)]);
+        assert_eq!(setup.hexes.len(), 14);
+        assert!(setup
+            .overlays
+            .iter()
+            .any(|overlay| overlay.hex == Hex::new(2, 1) && overlay.kind == OverlayKind::Obstacle));
+        assert!(setup
+            .overlays
+            .iter()
+            .any(|overlay| overlay.hex == Hex::new(2, 2)
+                && overlay.kind == OverlayKind::DifficultTerrain));
+
+        let elite = setup
+            .figures
+            .iter()
+            .find(|figure| figure.id == FigureId::new(2))
+            .unwrap();
+        assert_eq!(elite.hex, Hex::new(2, 2));
+        assert_eq!(elite.team, Team::Monster);
+        assert_eq!(elite.rank, Some(MonsterRank::Elite));
+    }
+
+    #[test]
+    fn indentation_does_not_move_hexes() {
+        let indented = parse_hex_map(
+            "
+            .   P0
+          .   .",
+        )
+        .unwrap();
+
+        assert_eq!(
+            indented.hexes,
+            vec![
+                Hex::new(0, 0),
+                Hex::new(1, 0),
+                Hex::new(-1, 1),
+                Hex::new(0, 1)
+            ]
+        );
+        assert_eq!(indented.figures[0].hex, Hex::new(1, 0));
+    }
+
+    #[test]
+    fn rejects_cells_between_hexes() {
+        assert_eq!(
+            parse_hex_map(".  .").unwrap_err(),
+            HexMapError::Misaligned { line: 1, column: 4 }
+        );
+        assert_eq!(
+            parse_hex_map(".   X").unwrap_err(),
+            HexMapError::UnknownCell {
+                line: 1,
+                cell: "X".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn prints_what_was_parsed() {
+        let (world, hex_grid, _) = spawn(MAP);
+
+        assert_eq!(print_hex_map(&world, hex_grid, &[]), MAP);
+    }
+
+    #[test]
+    fn prints_the_path_of_a_monster() {
+        let (world, hex_grid, figures) = spawn(
+            "\
+.   .   .   .   .
+  .   M1  O   .   P0
+O   O   O   O   O",
+        );
+        let monster = figures[&(FigureId::new(1), FigureInstance::new(0))];
+        let ability = MonsterAbility {
+            movement: 3,
+            ..default()
+        };
+
+        let turn = monster_turn(&world, monster, &ability).unwrap();
+        let path = find_move_path(
+            &world,
+            monster,
+            turn.destination,
+            ability.movement_kind,
+            turn.focus.unwrap().negative_is_obstacle,
+        )
+        .unwrap();
+
+        assert_eq!(
+            print_hex_map(&world, hex_grid, &path.hexes),
+            "\
+.   .   *   *   .
+  .   M1  O   *   P0
+O   O   O   O   O"
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use hexx::{algorithms::a_star, Hex};
+
+use crate::figure::{movement::MovementKind, Team};
+
+use super::{
+    line_of_sight::line_of_sight,
+    map::{HexGrid, HexLayer, OverlayKind},
+};
+
+/*
+    Everything on the HexGrid that matters for movement, detached from the world
+    so the AI can try out as many paths as it likes
+*/
+#[derive(Debug, Default, Clone)]
+pub struct MovementMap {
+    hexes: HashSet<Hex>,
+    overlays: HashMap<Hex, OverlayKind>,
+    figures: HashMap<Hex, Team>,
+    walls: HashSet<Hex>,
+}
+
+/* The hexes moved through including start and end, and the movement points needed for it */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    pub hexes: Vec<Hex>,
+    pub cost: u32,
+}
+
+impl MovementMap {
+    pub fn from_hex_grid(hex_grid: &HexGrid, world: &World) -> Self {
+        let mut movement_map = Self {
+            walls: hex_grid.walls().clone(),
+            ..default()
+        };
+
+        for (hex, _) in hex_grid.iter(&HexLayer::Ground) {
+            movement_map.insert_hex(hex);
+        }
+        for (hex, entity) in hex_grid.iter(&HexLayer::Overlay) {
+            if let Some(overlay_kind) = world.get::<OverlayKind>(entity) {
+                movement_map.insert_overlay(hex, *overlay_kind);
+            }
+        }
+        for (hex, entity) in hex_grid.iter(&HexLayer::Figure) {
+            if let Some(team) = world.get::<Team>(entity) {
+                movement_map.insert_figure(hex, *team);
+            }
+        }
+
+        movement_map
+    }
+
+    pub fn insert_hex(&mut self, hex: Hex) {
+        self.hexes.insert(hex);
+    }
+
+    pub fn insert_overlay(&mut self, hex: Hex, overlay_kind: OverlayKind) {
+        self.overlays.insert(hex, overlay_kind);
+    }
+
+    pub fn insert_figure(&mut self, hex: Hex, team: Team) {
+        self.figures.insert(hex, team);
+    }
+
+    pub fn remove_figure(&mut self, hex: &Hex) {
+        self.figures.remove(hex);
+    }
+
+    /* Walls are never part of the map, even if a hex was inserted there */
+    pub fn contains(&self, hex: &Hex) -> bool {
+        self.hexes.contains(hex) && !self.walls.contains(hex)
+    }
+
+    pub fn line_of_sight(&self, from: Hex, to: Hex) -> bool {
+        line_of_sight(&self.walls, from, to)
+    }
+
+    pub fn overlay(&self, hex: &Hex) -> Option<OverlayKind> {
+        self.overlays.get(hex).copied()
+    }
+
+    pub fn figure(&self, hex: &Hex) -> Option<Team> {
+        self.figures.get(hex).copied()
+    }
+
+    pub fn is_negative(&self, hex: &Hex) -> bool {
+        matches!(
+            self.overlay(hex),
+            Some(OverlayKind::HazardousTerrain | OverlayKind::Trap)
+        )
+    }
+
+    /// Cost to move through a hex on the way somewhere else, None if it can not be moved through
+    /// Jumping and flying ignore everything on the hexes they pass
+    fn pass_cost(
+        &self,
+        hex: &Hex,
+        team: Team,
+        movement_kind: MovementKind,
+        negative_is_obstacle: bool,
+    ) -> Option<u32> {
+        if !self.contains(hex) {
+            return None;
+        }
+
+        match movement_kind {
+            MovementKind::Default => {
+                if self
+                    .figure(hex)
+                    .is_some_and(|figure_team| !figure_team.is_ally_of(&team))
+                {
+                    return None;
+                }
+                if negative_is_obstacle && self.is_negative(hex) {
+                    return None;
+                }
+
+                match self.overlay(hex) {
+                    Some(OverlayKind::Obstacle) => None,
+                    Some(OverlayKind::DifficultTerrain) => Some(2),
+                    _ => Some(1),
+                }
+            }
+            MovementKind::Jump | MovementKind::Fly => Some(1),
+        }
+    }
+
+    /// Cost to end the movement in a hex, None if the movement can not end there
+    /// No movement can end on an obstacle or another figure
+    fn end_cost(
+        &self,
+        hex: &Hex,
+        team: Team,
+        movement_kind: MovementKind,
+        negative_is_obstacle: bool,
+    ) -> Option<u32> {
+        if !self.contains(hex)
+            || self.figure(hex).is_some()
+            || self.overlay(hex) == Some(OverlayKind::Obstacle)
+        {
+            return None;
+        }
+
+        match movement_kind {
+            MovementKind::Default => self.pass_cost(hex, team, movement_kind, negative_is_obstacle),
+            /* Jumping still lands in difficult terrain and negative hexes */
+            MovementKind::Jump => {
+                if negative_is_obstacle && self.is_negative(hex) {
+                    return None;
+                }
+
+                match self.overlay(hex) {
+                    Some(OverlayKind::DifficultTerrain) => Some(2),
+                    _ => Some(1),
+                }
+            }
+            MovementKind::Fly => Some(1),
+        }
+    }
+
+    /// Number of hexes from one hex to another, counting around walls but through anything else
+    pub fn proximity(&self, from: Hex, to: Hex) -> Option<u32> {
+        let hexes = a_star(from, to, |_, hex| {
+            (hex == from || hex == to || self.contains(&hex)).then_some(1)
+        })?;
+
+        Some(hexes.len() as u32 - 1)
+    }
+
+    /// Find the cheapest path from start to end for a figure of the given team
+    /// Negative hexes are moved through unless negative_is_obstacle is set, which the AI does first
+    pub fn find_path(
+        &self,
+        start: Hex,
+        end: Hex,
+        team: Team,
+        movement_kind: MovementKind,
+        negative_is_obstacle: bool,
+    ) -> Option<Path> {
+        if start == end {
+            return Some(Path {
+                hexes: vec![start],
+                cost: 0,
+            });
+        }
+
+        let cost = |_: Hex, to: Hex| {
+            if to == start {
+                Some(0)
+            } else if to == end {
+                self.end_cost(&to, team, movement_kind, negative_is_obstacle)
+            } else {
+                self.pass_cost(&to, team, movement_kind, negative_is_obstacle)
+            }
+        };
+        let hexes = a_star(start, end, cost)?;
+        let cost = hexes
+            .windows(2)
+            .filter_map(|step| cost(step[0], step[1]))
+            .sum();
+
+        Some(Path { hexes, cost })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        figure::{FigureId, FigureInstance},
+        scenario::{
+            map::HexPosition,
+            notation::{parse_hex_map, print_hex_map},
+        },
+    };
+
+    use super::*;
+
+    /* The cheapest path of P0 to the end, printed onto the map */
+    fn find_path(text: &str, end: Hex, negative_is_obstacle: bool) -> Option<(u32, String)> {
+        let mut world = World::new();
+        let figures = parse_hex_map(text).unwrap().spawn(&mut world.commands());
+        world.flush();
+        let (entity, hex_grid) = world.query::<(Entity, &HexGrid)>().single(&world);
+        let player = figures[&(FigureId::new(0), FigureInstance::new(0))];
+        let start = world.get::<HexPosition>(player).unwrap().hex();
+
+        let path = MovementMap::from_hex_grid(hex_grid, &world).find_path(
+            start,
+            end,
+            Team::Player,
+            MovementKind::Default,
+            negative_is_obstacle,
+        )?;
+
+        Some((path.cost, print_hex_map(&world, entity, &path.hexes)))
+    }
+
+    #[test]
+    fn difficult_terrain_costs_double() {
+        assert_eq!(
+            find_path("P0  D   .", Hex::new(2, 0), false),
+            Some((3, "P0  D   *".to_string()))
+        );
+    }
+
+    #[test]
+    fn difficult_terrain_is_walked_around_if_cheaper() {
+        assert_eq!(
+            find_path(
+                "\
+P0  D   D   .
+  .   .   .",
+                Hex::new(3, 0),
+                false
+            ),
+            Some((
+                4,
+                "\
+P0  D   D   *
+  *   *   *"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn obstacles_and_walls_are_walked_around() {
+        for blocker in ["O", "#"] {
+            let map = format!("P0  {}   .\n  .   .", blocker);
+            let expected = format!("P0  {}   *\n  *   *", blocker);
+
+            assert_eq!(find_path(&map, Hex::new(2, 0), false), Some((3, expected)));
+        }
+    }
+
+    #[test]
+    fn enemies_are_walked_around_and_allies_through() {
+        assert_eq!(
+            find_path(
+                "\
+P0  M1  .
+  .   .",
+                Hex::new(2, 0),
+                false
+            ),
+            Some((
+                3,
+                "\
+P0  M1  *
+  *   *"
+                    .to_string()
+            ))
+        );
+        assert_eq!(
+            find_path("P0  A1  .", Hex::new(2, 0), false),
+            Some((2, "P0  A1  *".to_string()))
+        );
+        assert_eq!(find_path("P0  A1  .", Hex::new(1, 0), false), None);
+    }
+
+    #[test]
+    fn negative_hexes_are_obstacles_only_if_asked() {
+        assert_eq!(
+            find_path("P0  T   .", Hex::new(2, 0), false),
+            Some((2, "P0  T   *".to_string()))
+        );
+        assert_eq!(find_path("P0  T   .", Hex::new(2, 0), true), None);
+    }
+}
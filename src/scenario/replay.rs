@@ -0,0 +1,267 @@
+use std::fs;
+
+use bevy::{
+    ecs::entity::MapEntities,
+    prelude::*,
+    reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer},
+    scene::ron,
+};
+use serde::de::DeserializeSeed;
+
+use crate::{
+    figure::{ai::MonsterAbilities, FigureId, FigureInstance, Initiatives},
+    game::{AppState, Round, ScenarioState, TurnOrder},
+};
+
+use super::{
+    command::ScenarioCommandQueue,
+    setup::{despawn_scenario, ScenarioSetup, SpawnedEntityMapper},
+};
+
+/* Replays are the initial scenario setup together with everything that happened since */
+/* Loading one rewinds to the start, so the history can be stepped through with redo */
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Replay>();
+
+        app.add_systems(Update, (save_replay, load_replay));
+    }
+}
+
+const REPLAY_PATH: &str = "replay.ron";
+
+#[derive(Reflect)]
+struct Replay {
+    setup: ScenarioSetup,
+    /* Entities in the recorded commands are matched to figures by FigureId and FigureInstance */
+    figures: Vec<ReplayFigure>,
+    queue: ScenarioCommandQueue,
+}
+
+#[derive(Debug, Reflect)]
+struct ReplayFigure {
+    entity: Entity,
+    id: FigureId,
+    instance: FigureInstance,
+}
+
+impl Replay {
+    fn from_world(world: &mut World) -> Option<Self> {
+        let setup = world.get_resource::<ScenarioSetup>()?.clone();
+        let queue = world.get_resource::<ScenarioCommandQueue>()?.rewound();
+        let figures = world
+            .query::<(Entity, &FigureId, &FigureInstance)>()
+            .iter(world)
+            .map(|(entity, id, instance)| ReplayFigure {
+                entity,
+                id: *id,
+                instance: *instance,
+            })
+            .collect();
+
+        Some(Self {
+            setup,
+            figures,
+            queue,
+        })
+    }
+
+    fn serialize(&self, world: &World) -> Result<String, ron::Error> {
+        let type_registry = world.resource::<AppTypeRegistry>().read();
+        let serializer = TypedReflectSerializer::new(self, &type_registry);
+
+        ron::ser::to_string_pretty(&serializer, ron::ser::PrettyConfig::default())
+    }
+
+    fn deserialize(world: &World, replay: &str) -> Result<Self, ron::Error> {
+        let type_registry = world.resource::<AppTypeRegistry>().read();
+        let reflect_deserializer = TypedReflectDeserializer::of::<Replay>(&type_registry);
+        let mut deserializer =
+            ron::de::Deserializer::from_str(replay).map_err(|error| error.code)?;
+
+        let replay = reflect_deserializer.deserialize(&mut deserializer)?;
+        Ok(Replay::from_reflect(replay.as_partial_reflect()).unwrap())
+    }
+
+    /// Replaces the running scenario with the initial setup of this replay
+    fn apply(self, world: &mut World) {
+        let Replay {
+            setup,
+            figures,
+            mut queue,
+        } = self;
+
+        despawn_scenario(world);
+
+        let spawned_figures = setup.spawn(&mut world.commands());
+        world.flush();
+
+        let mut entity_mapper = SpawnedEntityMapper(
+            figures
+                .iter()
+                .filter_map(|figure| {
+                    let entity = spawned_figures.get(&(figure.id, figure.instance))?;
+                    Some((figure.entity, *entity))
+                })
+                .collect(),
+        );
+        queue.map_entities(&mut entity_mapper);
+
+        world.insert_resource(setup);
+        world.insert_resource(queue);
+
+        /* Whatever round was running belongs to the replaced scenario, the replay starts at the first one */
+        world.insert_resource(Round::default());
+        world.insert_resource(TurnOrder::default());
+        world.insert_resource(Initiatives::default());
+        world.insert_resource(MonsterAbilities::default());
+        world
+            .resource_mut::<NextState<AppState>>()
+            .set(AppState::InGame);
+        world
+            .resource_mut::<NextState<ScenarioState>>()
+            .set(ScenarioState::Begin);
+    }
+}
+
+fn save_replay(world: &mut World) {
+    let keyboard_input = world.resource::<ButtonInput<KeyCode>>();
+    if !keyboard_input.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    let Some(replay) = Replay::from_world(world) else {
+        warn!("No scenario to save a replay of");
+        return;
+    };
+
+    match replay.serialize(world) {
+        Ok(replay) => match fs::write(REPLAY_PATH, replay) {
+            Ok(()) => info!("Saved replay to {}", REPLAY_PATH),
+            Err(error) => error!("Could not write replay: {}", error),
+        },
+        Err(error) => error!("Could not serialize replay: {}", error),
+    }
+}
+
+fn load_replay(world: &mut World) {
+    let keyboard_input = world.resource::<ButtonInput<KeyCode>>();
+    if !keyboard_input.just_pressed(KeyCode::F6) {
+        return;
+    }
+
+    let replay = match fs::read_to_string(REPLAY_PATH) {
+        Ok(replay) => replay,
+        Err(error) => {
+            error!("Could not read replay: {}", error);
+            return;
+        }
+    };
+
+    match Replay::deserialize(world, &replay) {
+        Ok(replay) => {
+            replay.apply(world);
+            info!("Loaded replay from {}", REPLAY_PATH);
+        }
+        Err(error) => error!("Could not deserialize replay: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        figure::bonus::{calculated_shield, AddBonusCommand, BonusDuration, Shield},
+        game::{EndTurnCommand, RoundState},
+        headless::HeadlessPlugins,
+        scenario::notation::parse_hex_map,
+    };
+
+    use super::*;
+
+    fn step(app: &mut App, frames: usize) {
+        for _ in 0..frames {
+            ScenarioCommandQueue::scope(app.world_mut(), |command_queue, world| {
+                command_queue.execute_all(world);
+            });
+            app.update();
+        }
+    }
+
+    fn figure(app: &mut App, id: u32) -> Entity {
+        app.world_mut()
+            .query::<(Entity, &FigureId)>()
+            .iter(app.world())
+            .find(|(_, figure_id)| **figure_id == FigureId::new(id))
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn loading_mid_round_starts_over_and_redoes_the_history() {
+        let mut app = App::new();
+        app.add_plugins((HeadlessPlugins, ReplayPlugin));
+        app.finish();
+        app.cleanup();
+
+        let setup = parse_hex_map("P0  .   M1").unwrap();
+        setup.spawn(&mut app.world_mut().commands());
+        app.world_mut().flush();
+        app.insert_resource(setup);
+        let mut initiatives = app.world_mut().resource_mut::<Initiatives>();
+        initiatives.set(FigureId::new(0), 10);
+        initiatives.set(FigureId::new(1), 20);
+        app.world_mut()
+            .resource_mut::<NextState<AppState>>()
+            .set(AppState::InGame);
+        step(&mut app, 10);
+
+        let player = figure(&mut app, 0);
+        app.world_mut()
+            .resource_mut::<ScenarioCommandQueue>()
+            .queue_transaction(vec![
+                AddBonusCommand::new(player, BonusDuration::Persistent)
+                    .with_shield(Shield(2))
+                    .into(),
+                EndTurnCommand::new(player).into(),
+            ]);
+        step(&mut app, 1);
+        assert!(app.world().resource::<TurnOrder>().current() != Some(player));
+
+        let replay = Replay::from_world(app.world_mut())
+            .unwrap()
+            .serialize(app.world())
+            .unwrap();
+        Replay::deserialize(app.world(), &replay)
+            .unwrap()
+            .apply(app.world_mut());
+        /* Only frames, the history is left for redo */
+        for _ in 0..5 {
+            app.update();
+        }
+
+        /* Back at the start of the first round, which waits until the history is stepped through */
+        assert_eq!(app.world().resource::<Round>().0, 1);
+        assert_eq!(
+            *app.world().resource::<State<RoundState>>().get(),
+            RoundState::StartOfRoundEffects
+        );
+        assert_eq!(app.world().resource::<TurnOrder>().current(), None);
+        assert_eq!(
+            app.world().resource::<Initiatives>().get(&FigureId::new(0)),
+            None
+        );
+
+        /* The recorded commands act on the figures spawned by the replay */
+        let player = figure(&mut app, 0);
+        assert_eq!(calculated_shield(app.world(), player), 0);
+        ScenarioCommandQueue::scope(app.world_mut(), |command_queue, world| {
+            command_queue.redo_transaction(world);
+            assert_eq!(calculated_shield(world, player), 2);
+
+            command_queue.undo_transaction(world);
+            assert_eq!(calculated_shield(world, player), 0);
+        });
+    }
+}
@@ -0,0 +1,49 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/* All randomness of a scenario is drawn from here, so replays and tests roll the same */
+/* Commands store the state before drawing, so undo can restore it exactly */
+#[derive(Debug, Default, Clone, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct ScenarioRng {
+    seed: u64,
+    /* Position in the random stream, together with the seed this is the whole state */
+    /* ChaCha counts in u128, but ron only handles that with its integer128 feature, which is not enabled here */
+    /* 2^64 words are far more than any scenario draws, so u64 loses nothing */
+    word_pos: u64,
+}
+
+impl ScenarioRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, word_pos: 0 }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.word_pos
+    }
+
+    pub fn restore(&mut self, state: u64) {
+        self.word_pos = state;
+    }
+
+    pub fn gen_range(&mut self, range: Range<usize>) -> usize {
+        self.draw(|rng| rng.gen_range(range))
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        self.draw(|rng| items.shuffle(rng));
+    }
+
+    fn draw<R>(&mut self, f: impl FnOnce(&mut ChaCha8Rng) -> R) -> R {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        rng.set_word_pos(self.word_pos as u128);
+
+        let value = f(&mut rng);
+        self.word_pos = rng.get_word_pos() as u64;
+
+        value
+    }
+}
@@ -0,0 +1,586 @@
+use std::fs;
+
+use bevy::{
+    ecs::entity::MapEntities,
+    prelude::*,
+    reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer},
+    scene::ron,
+};
+use hexx::{Hex, HexLayout};
+use serde::{de::DeserializeSeed, Deserialize};
+
+use crate::{
+    figure::{
+        ai::MonsterAbilities,
+        bonus::{spawn_bonus, BonusDuration, BonusId, Retaliate, Shield},
+        condition::Conditions,
+        health::Health,
+        modifier::{deck::ModifierDeck, ModifierTray},
+        ActiveBonuses, FigureId, FigureInstance, Initiatives, MonsterRank, Summon, Team,
+    },
+    game::{AppState, RestoringSave, Round, RoundState, ScenarioState, TurnOrder},
+};
+
+use super::{
+    command::ScenarioCommandQueue,
+    element::Elements,
+    map::{HexGrid, HexLayer, HexPosition, OverlayKind},
+    rng::ScenarioRng,
+    setup::{despawn_scenario, FigureSetup, OverlaySetup, ScenarioSetup, SpawnedEntityMapper},
+};
+
+/* Saves the running scenario, so it can be continued another time */
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SaveGame>();
+
+        app.add_systems(Update, (save_game, load_game));
+    }
+}
+
+const SAVE_PATH: &str = "save.ron";
+
+/* Bump this whenever the layout of SaveGame changes */
+const SAVE_VERSION: u32 = 1;
+
+#[derive(Reflect)]
+struct SaveGame {
+    version: u32,
+    /* Kept so replays still start from the very beginning */
+    setup: ScenarioSetup,
+    layout: HexLayout,
+    hexes: Vec<Hex>,
+    walls: Vec<Hex>,
+    overlays: Vec<OverlaySetup>,
+    figures: Vec<SavedFigure>,
+    modifier_trays: Vec<ModifierTray>,
+    modifier_decks: Vec<ModifierDeck>,
+    round: Option<Round>,
+    /* A save made mid-turn continues in that turn */
+    scenario_state: Option<ScenarioState>,
+    round_state: Option<RoundState>,
+    turn_order: TurnOrder,
+    initiatives: Initiatives,
+    monster_abilities: MonsterAbilities,
+    queue: ScenarioCommandQueue,
+    rng: ScenarioRng,
+    elements: Elements,
+}
+
+#[derive(Debug, Reflect)]
+struct SavedFigure {
+    /* Entities in the queued commands are mapped to the respawned figures */
+    entity: Entity,
+    id: FigureId,
+    instance: FigureInstance,
+    team: Team,
+    hex: Hex,
+    health: Health,
+    conditions: Conditions,
+    shield: Shield,
+    retaliate: Option<Retaliate>,
+    bonuses: Vec<SavedBonus>,
+    rank: Option<MonsterRank>,
+    summon: bool,
+}
+
+#[derive(Debug, Reflect)]
+struct SavedBonus {
+    id: BonusId,
+    duration: BonusDuration,
+    shield: Option<Shield>,
+    retaliate: Option<Retaliate>,
+}
+
+/* Only the version is read first, so older save files can be rejected with a proper message */
+#[derive(Deserialize)]
+struct SaveGameVersion {
+    version: u32,
+}
+
+#[derive(Debug)]
+enum SaveError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+    Version(u32),
+    NoScenario,
+    UnknownFigure(FigureId, FigureInstance),
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(error) => write!(f, "{}", error),
+            SaveError::Ron(error) => write!(f, "{}", error),
+            SaveError::Version(version) => write!(
+                f,
+                "save version {} is not supported, expected {}",
+                version, SAVE_VERSION
+            ),
+            SaveError::NoScenario => write!(f, "there is no running scenario"),
+            SaveError::UnknownFigure(id, instance) => {
+                write!(f, "figure {:?} {:?} was not spawned", id, instance)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for SaveError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<ron::Error> for SaveError {
+    fn from(error: ron::Error) -> Self {
+        Self::Ron(error)
+    }
+}
+
+impl From<ron::de::SpannedError> for SaveError {
+    fn from(error: ron::de::SpannedError) -> Self {
+        Self::Ron(error.code)
+    }
+}
+
+impl SaveGame {
+    fn from_world(world: &mut World) -> Result<Self, SaveError> {
+        let setup = world
+            .get_resource::<ScenarioSetup>()
+            .ok_or(SaveError::NoScenario)?
+            .clone();
+        let (hex_grid, layout, walls) = world
+            .query::<(Entity, &HexGrid)>()
+            .iter(world)
+            .map(|(entity, hex_grid)| {
+                (
+                    entity,
+                    hex_grid.layout().clone(),
+                    hex_grid.walls().iter().copied().collect(),
+                )
+            })
+            .next()
+            .ok_or(SaveError::NoScenario)?;
+
+        let hexes = world
+            .query::<(&HexPosition, &Parent)>()
+            .iter(world)
+            .filter(|(hex_position, parent)| {
+                parent.get() == hex_grid && matches!(hex_position.layer(), HexLayer::Ground)
+            })
+            .map(|(hex_position, _)| hex_position.hex())
+            .collect();
+
+        let overlays = world
+            .query::<(&HexPosition, &OverlayKind)>()
+            .iter(world)
+            .map(|(hex_position, overlay_kind)| OverlaySetup {
+                hex: hex_position.hex(),
+                kind: *overlay_kind,
+            })
+            .collect();
+
+        let figures = world
+            .query::<(
+                Entity,
+                &FigureId,
+                &FigureInstance,
+                &Team,
+                &HexPosition,
+                &Health,
+                &Conditions,
+                &Shield,
+                Option<&Retaliate>,
+                Option<&MonsterRank>,
+                Has<Summon>,
+            )>()
+            .iter(world)
+            .map(
+                |(
+                    entity,
+                    id,
+                    instance,
+                    team,
+                    hex_position,
+                    health,
+                    conditions,
+                    shield,
+                    retaliate,
+                    rank,
+                    summon,
+                )| {
+                    SavedFigure {
+                        entity,
+                        id: *id,
+                        instance: *instance,
+                        team: *team,
+                        hex: hex_position.hex(),
+                        health: health.clone(),
+                        conditions: conditions.clone(),
+                        shield: *shield,
+                        retaliate: retaliate.copied(),
+                        bonuses: saved_bonuses(world, entity),
+                        rank: rank.copied(),
+                        summon,
+                    }
+                },
+            )
+            .collect();
+
+        let modifier_trays = world
+            .query::<&ModifierTray>()
+            .iter(world)
+            .cloned()
+            .collect();
+        let modifier_decks = world
+            .query::<&ModifierDeck>()
+            .iter(world)
+            .cloned()
+            .collect();
+
+        let round = world.get_resource::<Round>().cloned();
+        let scenario_state = world
+            .get_resource::<State<ScenarioState>>()
+            .map(|state| *state.get());
+        let round_state = world
+            .get_resource::<State<RoundState>>()
+            .map(|state| *state.get());
+        let turn_order = world
+            .get_resource::<TurnOrder>()
+            .cloned()
+            .unwrap_or_default();
+        let initiatives = world
+            .get_resource::<Initiatives>()
+            .cloned()
+            .unwrap_or_default();
+        let monster_abilities = world
+            .get_resource::<MonsterAbilities>()
+            .cloned()
+            .unwrap_or_default();
+        let queue = world
+            .get_resource::<ScenarioCommandQueue>()
+            .ok_or(SaveError::NoScenario)?
+            .clone();
+        let rng = world
+            .get_resource::<ScenarioRng>()
+            .ok_or(SaveError::NoScenario)?
+            .clone();
+        let elements = world
+            .get_resource::<Elements>()
+            .cloned()
+            .unwrap_or_default();
+
+        Ok(Self {
+            version: SAVE_VERSION,
+            setup,
+            layout,
+            hexes,
+            walls,
+            overlays,
+            figures,
+            modifier_trays,
+            modifier_decks,
+            round,
+            scenario_state,
+            round_state,
+            turn_order,
+            initiatives,
+            monster_abilities,
+            queue,
+            rng,
+            elements,
+        })
+    }
+
+    fn serialize(&self, world: &World) -> Result<String, SaveError> {
+        let type_registry = world.resource::<AppTypeRegistry>().read();
+        let serializer = TypedReflectSerializer::new(self, &type_registry);
+
+        Ok(ron::ser::to_string_pretty(
+            &serializer,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    fn deserialize(world: &World, save_game: &str) -> Result<Self, SaveError> {
+        let SaveGameVersion { version } = ron::from_str(save_game)?;
+        if version != SAVE_VERSION {
+            return Err(SaveError::Version(version));
+        }
+
+        let type_registry = world.resource::<AppTypeRegistry>().read();
+        let reflect_deserializer = TypedReflectDeserializer::of::<SaveGame>(&type_registry);
+        let mut deserializer = ron::de::Deserializer::from_str(save_game)?;
+
+        let save_game = reflect_deserializer.deserialize(&mut deserializer)?;
+        Ok(SaveGame::from_reflect(save_game.as_partial_reflect()).unwrap())
+    }
+
+    /// Replaces the running scenario with this one
+    fn apply(self, world: &mut World) -> Result<(), SaveError> {
+        let SaveGame {
+            setup,
+            layout,
+            hexes,
+            walls,
+            overlays,
+            figures,
+            modifier_trays,
+            modifier_decks,
+            round,
+            scenario_state,
+            round_state,
+            mut turn_order,
+            initiatives,
+            monster_abilities,
+            mut queue,
+            rng,
+            elements,
+            ..
+        } = self;
+
+        despawn_scenario(world);
+
+        /* Rebuild the hierarchy as if it was a fresh scenario and restore the figures afterwards */
+        let saved_setup = ScenarioSetup {
+            layout,
+            hexes,
+            walls,
+            overlays,
+            figures: figures
+                .iter()
+                .map(|figure| FigureSetup {
+                    id: figure.id,
+                    instance: figure.instance,
+                    team: figure.team,
+                    hex: figure.hex,
+                    health: figure.health.max(),
+                    shield: figure.shield.0,
+                    retaliate: figure.retaliate,
+                    immunities: figure.conditions.immunities().collect(),
+                    rank: figure.rank,
+                    summon: figure.summon,
+                })
+                .collect(),
+            modifier_trays,
+            modifier_decks,
+            goals: setup.goals.clone(),
+            special_rules: setup.special_rules.clone(),
+            seed: setup.seed,
+        };
+        let spawned_figures = saved_setup.spawn(&mut world.commands());
+        world.flush();
+
+        let mut entity_mapper = SpawnedEntityMapper(Default::default());
+        let (mut dead, mut living) = (vec![], vec![]);
+        for figure in figures {
+            let entity = *spawned_figures
+                .get(&(figure.id, figure.instance))
+                .ok_or(SaveError::UnknownFigure(figure.id, figure.instance))?;
+
+            let mut active_bonuses = ActiveBonuses::default();
+            for bonus in figure.bonuses {
+                active_bonuses.insert(spawn_bonus(
+                    world,
+                    entity,
+                    bonus.id,
+                    bonus.duration,
+                    bonus.shield,
+                    bonus.retaliate,
+                ));
+            }
+
+            if figure.health.is_dead() {
+                dead.push((figure.hex, entity));
+            } else {
+                living.push((figure.hex, entity));
+            }
+
+            world
+                .entity_mut(entity)
+                .insert((figure.health, figure.conditions, active_bonuses));
+            entity_mapper.0.insert(figure.entity, entity);
+        }
+        /* Dead figures are not on the map, whoever stands on their hex now is */
+        let dead = dead.into_iter().map(|(hex, entity)| (hex, entity, false));
+        let living = living.into_iter().map(|(hex, entity)| (hex, entity, true));
+        for (hex, entity, alive) in dead.chain(living) {
+            let hex_grid = world.get::<Parent>(entity).unwrap().get();
+            let mut hex_grid = world.get_mut::<HexGrid>(hex_grid).unwrap();
+            if alive {
+                hex_grid.insert(hex, &HexLayer::Figure, entity);
+            } else {
+                hex_grid.remove(&hex, &HexLayer::Figure);
+            }
+        }
+        queue.map_entities(&mut entity_mapper);
+        turn_order.map_entities(&mut entity_mapper);
+
+        world.insert_resource(setup);
+        world.insert_resource(queue);
+        world.insert_resource(rng);
+        world.insert_resource(elements);
+        world.insert_resource(turn_order);
+        world.insert_resource(initiatives);
+        world.insert_resource(monster_abilities);
+        if let Some(round) = round {
+            world.insert_resource(round);
+        }
+
+        /* The states are entered again without repeating what entering them already did */
+        world.insert_resource(RestoringSave);
+        world
+            .resource_mut::<NextState<AppState>>()
+            .set(AppState::InGame);
+        if let Some(scenario_state) = scenario_state {
+            world
+                .resource_mut::<NextState<ScenarioState>>()
+                .set(scenario_state);
+        }
+        if let Some(round_state) = round_state {
+            world
+                .resource_mut::<NextState<RoundState>>()
+                .set(round_state);
+        }
+
+        Ok(())
+    }
+}
+
+/* Bonuses are children of the figure, expired ones are despawned and only ActiveBonuses are left */
+fn saved_bonuses(world: &World, figure: Entity) -> Vec<SavedBonus> {
+    let Some(active_bonuses) = world.get::<ActiveBonuses>(figure) else {
+        return vec![];
+    };
+
+    active_bonuses
+        .iter()
+        .filter_map(|bonus| {
+            Some(SavedBonus {
+                id: *world.get::<BonusId>(bonus)?,
+                duration: *world.get::<BonusDuration>(bonus)?,
+                shield: world.get::<Shield>(bonus).copied(),
+                retaliate: world.get::<Retaliate>(bonus).copied(),
+            })
+        })
+        .collect()
+}
+
+fn save_game(world: &mut World) {
+    let keyboard_input = world.resource::<ButtonInput<KeyCode>>();
+    if !keyboard_input.just_pressed(KeyCode::F7) {
+        return;
+    }
+
+    let result = SaveGame::from_world(world)
+        .and_then(|save_game| save_game.serialize(world))
+        .and_then(|save_game| Ok(fs::write(SAVE_PATH, save_game)?));
+
+    match result {
+        Ok(()) => info!("Saved scenario to {}", SAVE_PATH),
+        Err(error) => error!("Could not save scenario: {}", error),
+    }
+}
+
+fn load_game(world: &mut World) {
+    let keyboard_input = world.resource::<ButtonInput<KeyCode>>();
+    if !keyboard_input.just_pressed(KeyCode::F8) {
+        return;
+    }
+
+    let result = fs::read_to_string(SAVE_PATH)
+        .map_err(SaveError::from)
+        .and_then(|save_game| SaveGame::deserialize(world, &save_game))
+        .and_then(|save_game| save_game.apply(world));
+
+    match result {
+        Ok(()) => info!("Loaded scenario from {}", SAVE_PATH),
+        Err(error) => error!("Could not load scenario: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        figure::condition::ConditionKind, game::EndTurnCommand, headless::HeadlessPlugins,
+        scenario::notation::parse_hex_map,
+    };
+
+    use super::*;
+
+    fn step(app: &mut App, frames: usize) {
+        for _ in 0..frames {
+            app.world_mut().resource_scope(
+                |world, mut command_queue: Mut<ScenarioCommandQueue>| {
+                    command_queue.execute_all(world);
+                },
+            );
+            app.update();
+        }
+    }
+
+    fn current_turn(app: &mut App) -> Option<FigureId> {
+        let entity = app.world().resource::<TurnOrder>().current()?;
+        app.world().get::<FigureId>(entity).copied()
+    }
+
+    #[test]
+    fn loading_continues_the_saved_turn() {
+        let mut app = App::new();
+        app.add_plugins((HeadlessPlugins, SavePlugin));
+        app.finish();
+        app.cleanup();
+
+        let mut setup = parse_hex_map("P0  .   M1").unwrap();
+        setup.figures[1].immunities = vec![ConditionKind::Poison];
+        let figures = setup.spawn(&mut app.world_mut().commands());
+        app.world_mut().flush();
+        app.insert_resource(setup);
+        let player = figures[&(FigureId::new(0), FigureInstance::new(0))];
+
+        let mut initiatives = app.world_mut().resource_mut::<Initiatives>();
+        initiatives.set(FigureId::new(0), 10);
+        initiatives.set(FigureId::new(1), 20);
+        app.world_mut()
+            .resource_mut::<NextState<AppState>>()
+            .set(AppState::InGame);
+        step(&mut app, 10);
+
+        let save_game = SaveGame::from_world(app.world_mut())
+            .and_then(|save_game| save_game.serialize(app.world()))
+            .unwrap();
+
+        /* Play on into the next round before loading */
+        app.world_mut()
+            .resource_mut::<ScenarioCommandQueue>()
+            .queue(vec![EndTurnCommand::new(player).into()]);
+        step(&mut app, 10);
+        assert_eq!(
+            *app.world().resource::<State<RoundState>>().get(),
+            RoundState::CardSelection
+        );
+
+        SaveGame::deserialize(app.world(), &save_game)
+            .unwrap()
+            .apply(app.world_mut())
+            .unwrap();
+        step(&mut app, 3);
+
+        assert_eq!(
+            *app.world().resource::<State<RoundState>>().get(),
+            RoundState::CharacterAndMonsterTurns
+        );
+        assert_eq!(current_turn(&mut app), Some(FigureId::new(0)));
+        assert!(app.world().get_resource::<RestoringSave>().is_none());
+
+        let (_, conditions) = app
+            .world_mut()
+            .query::<(&FigureId, &Conditions)>()
+            .iter(app.world())
+            .find(|(id, _)| **id == FigureId::new(1))
+            .unwrap();
+        assert!(conditions.is_immune(ConditionKind::Poison));
+    }
+}
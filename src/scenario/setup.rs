@@ -0,0 +1,181 @@
+use bevy::{
+    ecs::entity::{EntityHashMap, EntityMapper},
+    prelude::*,
+    utils::HashMap,
+};
+use hexx::{Hex, HexLayout};
+use serde::Deserialize;
+
+use crate::figure::{
+    bonus::{Retaliate, Shield},
+    condition::{ConditionKind, Conditions},
+    health::Health,
+    modifier::{deck::ModifierDeck, ModifierTray},
+    FigureBundle, FigureId, FigureInstance, MonsterRank, Summon, Team,
+};
+
+use super::{
+    element::Elements,
+    map::{HexGrid, HexLayer, HexPosition, OverlayKind},
+    rng::ScenarioRng,
+};
+
+/* Everything needed to spawn a scenario from scratch */
+/* It is kept around as resource, so what happened since can be replayed on top of it */
+#[derive(Debug, Clone, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct ScenarioSetup {
+    pub layout: HexLayout,
+    pub hexes: Vec<Hex>,
+    pub walls: Vec<Hex>,
+    pub overlays: Vec<OverlaySetup>,
+    pub figures: Vec<FigureSetup>,
+    pub modifier_trays: Vec<ModifierTray>,
+    pub modifier_decks: Vec<ModifierDeck>,
+    pub goals: Vec<ScenarioGoal>,
+    /* Special rules are only shown to the players for now */
+    pub special_rules: Vec<String>,
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Reflect, Deserialize)]
+pub struct OverlaySetup {
+    pub hex: Hex,
+    pub kind: OverlayKind,
+}
+
+#[derive(Debug, Clone, Reflect)]
+pub struct FigureSetup {
+    pub id: FigureId,
+    pub instance: FigureInstance,
+    pub team: Team,
+    pub hex: Hex,
+    pub health: usize,
+    pub shield: usize,
+    pub retaliate: Option<Retaliate>,
+    pub immunities: Vec<ConditionKind>,
+    pub rank: Option<MonsterRank>,
+    /* Summons share the FigureId of the character that summoned them */
+    pub summon: bool,
+}
+
+/* Which game the scenario is played by, this decides where the modifiers come from */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect, Deserialize)]
+pub enum Ruleset {
+    #[default]
+    ButtonsAndBugs,
+    Gloomhaven,
+}
+
+/* TODO: Check these at the end of each round */
+#[derive(Debug, Clone, Reflect, Deserialize)]
+pub enum ScenarioGoal {
+    KillAllEnemies,
+    SurviveRounds(usize),
+    /* Anything else has to be checked by the players */
+    Custom(String),
+}
+
+impl ScenarioSetup {
+    /// Spawn the HexGrid hierarchy and modifier sources, seed the ScenarioRng and clear the elements
+    /// Returns the spawned figures by FigureId and FigureInstance
+    pub fn spawn(&self, commands: &mut Commands) -> HashMap<(FigureId, FigureInstance), Entity> {
+        let mut hex_grid = HexGrid::new(self.layout.clone());
+        for wall in &self.walls {
+            hex_grid.insert_wall(*wall);
+        }
+
+        let mut tile_entities: Vec<Entity> = self
+            .hexes
+            .iter()
+            .map(|hex| {
+                let entity = commands
+                    .spawn(HexPosition::new(*hex, HexLayer::Ground))
+                    .id();
+                hex_grid.insert(*hex, &HexLayer::Ground, entity);
+
+                entity
+            })
+            .collect();
+        tile_entities.extend(self.overlays.iter().map(|overlay| {
+            let entity = commands
+                .spawn((
+                    HexPosition::new(overlay.hex, HexLayer::Overlay),
+                    overlay.kind,
+                ))
+                .id();
+            hex_grid.insert(overlay.hex, &HexLayer::Overlay, entity);
+
+            entity
+        }));
+
+        let figure_entities: HashMap<(FigureId, FigureInstance), Entity> = self
+            .figures
+            .iter()
+            .map(|figure| {
+                let mut entity = commands.spawn(FigureBundle {
+                    hex_position: HexPosition::new(figure.hex, HexLayer::Figure),
+                    health: Health::new(figure.health),
+                    conditions: Conditions::new(&figure.immunities),
+                    shield: Shield(figure.shield),
+                    active_bonuses: Default::default(),
+                    team: figure.team,
+                    id: figure.id,
+                    instance: figure.instance,
+                });
+                if let Some(rank) = figure.rank {
+                    entity.insert(rank);
+                }
+                if let Some(retaliate) = figure.retaliate {
+                    entity.insert(retaliate);
+                }
+                if figure.summon {
+                    entity.insert(Summon);
+                }
+                let entity = entity.id();
+                hex_grid.insert(figure.hex, &HexLayer::Figure, entity);
+
+                ((figure.id, figure.instance), entity)
+            })
+            .collect();
+
+        for modifier_tray in &self.modifier_trays {
+            commands.spawn(modifier_tray.clone());
+        }
+        for modifier_deck in &self.modifier_decks {
+            commands.spawn(modifier_deck.clone());
+        }
+
+        commands.insert_resource(ScenarioRng::new(self.seed));
+        commands.insert_resource(Elements::default());
+
+        commands
+            .spawn(hex_grid)
+            .add_children(&tile_entities)
+            .add_children(&figure_entities.values().copied().collect::<Vec<_>>());
+
+        figure_entities
+    }
+}
+
+/// Despawn the HexGrid hierarchy and modifier sources of the running scenario
+pub fn despawn_scenario(world: &mut World) {
+    let entities: Vec<Entity> = world
+        .query_filtered::<Entity, Or<(With<HexGrid>, With<ModifierTray>, With<ModifierDeck>)>>()
+        .iter(world)
+        .collect();
+
+    for entity in entities {
+        despawn_with_children_recursive(world, entity, true);
+    }
+}
+
+/* Maps entities of a saved scenario to the ones spawned when loading it */
+/* Entities without a match were not spawned again, they must not end up as some unrelated new entity */
+pub struct SpawnedEntityMapper(pub EntityHashMap<Entity>);
+
+impl EntityMapper for SpawnedEntityMapper {
+    fn map_entity(&mut self, entity: Entity) -> Entity {
+        self.0.get(&entity).copied().unwrap_or(Entity::PLACEHOLDER)
+    }
+}